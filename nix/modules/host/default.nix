@@ -29,6 +29,7 @@ in
           Type = "simple";
           ExecStart = "${lib.getExe self.packages.${pkgs.stdenv.hostPlatform.system}.containd}";
           Restart = "always";
+          StateDirectory = "contain";
         };
       };
     })
@@ -22,6 +22,9 @@ rec {
     makeWrapper ${contain-unwrapped}/bin/containd $out/bin/containd \
       --set PATH ${pkgs.lib.makeBinPath [ pkgs.iproute2 ]}
   '');
+  schema = pkgs.runCommand "contain-schema.json" { } ''
+    ${contain-unwrapped}/bin/contain schema > $out
+  '';
 
   cloud-hypervisor-graphics = import ./cloud-hypervisor-graphics pkgs;
   crosvm-gpu-only = import ./crosvm-gpu-only pkgs;
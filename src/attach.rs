@@ -0,0 +1,373 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::select;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{broadcast, mpsc as async_mpsc};
+
+use crate::instance::{self, InstanceError};
+
+pub static CONSOLE_SOCKET: &str = "console.sock";
+pub static SERIAL_SOCKET: &str = "serial.sock";
+
+const ESCAPE: u8 = 0x1d;
+
+const MODE_INTERACTIVE: u8 = b'w';
+const MODE_READ_ONLY: u8 = b'r';
+const ACCEPTED: u8 = b'+';
+const REJECTED: u8 = b'-';
+
+const FRAME_DATA: u8 = 0;
+const FRAME_RESIZE: u8 = 1;
+
+#[derive(Error, Debug)]
+pub enum AttachError {
+    #[error("unable to find vm")]
+    Instance(#[from] InstanceError),
+    #[error("vm {id} has no {device} socket, start it with the {device} sink set to socket")]
+    NoSocket { id: String, device: String },
+    #[error("console of vm {id} refused the attach: {reason}")]
+    Rejected { id: String, reason: String },
+    #[error("console connection failed")]
+    Io(#[from] io::Error),
+}
+
+enum Input {
+    Data(Vec<u8>),
+    Resize(u16, u16),
+}
+
+pub(crate) type Sink = Box<dyn FnMut(&[u8]) + Send>;
+
+pub(crate) struct Pty {
+    file: File,
+    output: broadcast::Sender<Arc<[u8]>>,
+}
+
+impl Pty {
+    pub(crate) fn open(path: &Path, mut sinks: Vec<Sink>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)?;
+        make_raw(file.as_raw_fd())?;
+
+        let (output, _) = broadcast::channel::<Arc<[u8]>>(256);
+        let mut reader = file.try_clone()?;
+        let reader_tx = output.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        for sink in sinks.iter_mut() {
+                            sink(&buf[..n]);
+                        }
+                        _ = reader_tx.send(buf[..n].into());
+                    }
+                }
+            }
+        });
+        Ok(Self { file, output })
+    }
+}
+
+pub(crate) async fn serve(socket: PathBuf, pty: Pty) -> io::Result<()> {
+    let output_tx = pty.output;
+    let (input_tx, input_rx) = mpsc::channel::<Input>();
+    let mut writer = pty.file;
+    thread::spawn(move || {
+        for input in input_rx {
+            match input {
+                Input::Data(data) => {
+                    if writer.write_all(&data).is_err() {
+                        break;
+                    }
+                }
+                Input::Resize(rows, cols) => set_size(writer.as_raw_fd(), rows, cols),
+            }
+        }
+    });
+
+    let listener = UnixListener::bind(&socket)?;
+    let interactive = Arc::new(AtomicBool::new(false));
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(client(
+            stream,
+            output_tx.subscribe(),
+            input_tx.clone(),
+            interactive.clone(),
+        ));
+    }
+}
+
+async fn client(
+    mut stream: UnixStream,
+    mut output_rx: broadcast::Receiver<Arc<[u8]>>,
+    input_tx: mpsc::Sender<Input>,
+    interactive: Arc<AtomicBool>,
+) {
+    let Ok(mode) = stream.read_u8().await else {
+        return;
+    };
+    let writes = mode == MODE_INTERACTIVE;
+    if writes
+        && interactive
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+    {
+        _ = stream.write_u8(REJECTED).await;
+        _ = stream
+            .write_all(b"another client is attached interactively, use --read-only\n")
+            .await;
+        return;
+    }
+    if stream.write_u8(ACCEPTED).await.is_ok() {
+        forward(stream, &mut output_rx, &input_tx, writes).await;
+    }
+    if writes {
+        interactive.store(false, Ordering::SeqCst);
+    }
+}
+
+async fn forward(
+    stream: UnixStream,
+    output_rx: &mut broadcast::Receiver<Arc<[u8]>>,
+    input_tx: &mpsc::Sender<Input>,
+    writes: bool,
+) {
+    let (mut read, mut write) = stream.into_split();
+    let output = async {
+        loop {
+            match output_rx.recv().await {
+                Ok(data) => {
+                    if write.write_all(&data).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    };
+    let input = async {
+        while let Ok(input) = read_frame(&mut read).await {
+            if writes && input_tx.send(input).is_err() {
+                break;
+            }
+        }
+    };
+    select! {
+        _ = output => {},
+        _ = input => {},
+    }
+}
+
+async fn read_frame(read: &mut (impl AsyncReadExt + Unpin)) -> io::Result<Input> {
+    let kind = read.read_u8().await?;
+    let len = read.read_u16().await?;
+    let mut payload = vec![0; len as usize];
+    read.read_exact(&mut payload).await?;
+    match (kind, payload.as_slice()) {
+        (FRAME_RESIZE, [r0, r1, c0, c1]) => Ok(Input::Resize(
+            u16::from_be_bytes([*r0, *r1]),
+            u16::from_be_bytes([*c0, *c1]),
+        )),
+        _ => Ok(Input::Data(payload)),
+    }
+}
+
+async fn write_frame(
+    write: &mut (impl AsyncWriteExt + Unpin),
+    kind: u8,
+    payload: &[u8],
+) -> io::Result<()> {
+    for chunk in payload.chunks(u16::MAX as usize) {
+        write.write_u8(kind).await?;
+        write.write_u16(chunk.len() as u16).await?;
+        write.write_all(chunk).await?;
+    }
+    Ok(())
+}
+
+pub async fn attach(vm: &str, serial: bool, read_only: bool) -> Result<(), AttachError> {
+    let instance = instance::find(vm)?;
+    let id = instance.info.id.clone();
+    let (device, socket) = match serial {
+        true => ("serial", SERIAL_SOCKET),
+        false => ("console", CONSOLE_SOCKET),
+    };
+    let socket = instance.dir.join(socket);
+    if !socket.exists() {
+        return Err(AttachError::NoSocket {
+            id,
+            device: device.to_string(),
+        });
+    }
+
+    let mut stream = UnixStream::connect(&socket).await?;
+    stream
+        .write_u8(if read_only {
+            MODE_READ_ONLY
+        } else {
+            MODE_INTERACTIVE
+        })
+        .await?;
+    if stream.read_u8().await? != ACCEPTED {
+        let mut reason = String::new();
+        stream.read_to_string(&mut reason).await?;
+        return Err(AttachError::Rejected {
+            id,
+            reason: reason.trim().to_string(),
+        });
+    }
+
+    eprintln!(
+        "attached to {}{}, press Ctrl-] to detach",
+        id,
+        if read_only { " read-only" } else { "" }
+    );
+    let raw = RawMode::enable(libc::STDIN_FILENO);
+
+    let (mut read, mut write) = stream.into_split();
+    if !read_only {
+        if let Some((rows, cols)) = get_size(libc::STDIN_FILENO) {
+            write_frame(&mut write, FRAME_RESIZE, &resize_payload(rows, cols)).await?;
+        }
+    }
+
+    let (stdin_tx, mut stdin_rx) = async_mpsc::channel::<Vec<u8>>(16);
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        let mut buf = [0; 1024];
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if stdin_tx.blocking_send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let mut window_change = signal(SignalKind::window_change())?;
+    let mut stdout = tokio::io::stdout();
+    let mut buf = [0; 4096];
+    let mut stdin_open = true;
+    let result = loop {
+        select! {
+            n = read.read(&mut buf) => match n {
+                Ok(0) => break Ok("console closed"),
+                Ok(n) => {
+                    stdout.write_all(&buf[..n]).await?;
+                    stdout.flush().await?;
+                }
+                Err(e) => break Err(e),
+            },
+            input = stdin_rx.recv(), if stdin_open => {
+                let Some(input) = input else {
+                    if read_only {
+                        stdin_open = false;
+                        continue;
+                    }
+                    break Ok("detached");
+                };
+                let escape = input.iter().position(|b| *b == ESCAPE);
+                let data = &input[..escape.unwrap_or(input.len())];
+                if !read_only && !data.is_empty() {
+                    write_frame(&mut write, FRAME_DATA, data).await?;
+                }
+                if escape.is_some() {
+                    break Ok("detached");
+                }
+            },
+            _ = window_change.recv() => {
+                if let (false, Some((rows, cols))) = (read_only, get_size(libc::STDIN_FILENO)) {
+                    write_frame(&mut write, FRAME_RESIZE, &resize_payload(rows, cols)).await?;
+                }
+            },
+        }
+    };
+
+    drop(raw);
+    eprintln!();
+    eprintln!("{} from {}", result?, id);
+    Ok(())
+}
+
+fn resize_payload(rows: u16, cols: u16) -> Vec<u8> {
+    [rows.to_be_bytes(), cols.to_be_bytes()].concat()
+}
+
+struct RawMode {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable(fd: RawFd) -> Option<Self> {
+        let original = get_attr(fd)?;
+        make_raw(fd).ok()?;
+        Some(Self { fd, original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+fn get_attr(fd: RawFd) -> Option<libc::termios> {
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return None;
+    }
+    Some(termios)
+}
+
+fn make_raw(fd: RawFd) -> io::Result<()> {
+    let mut termios = get_attr(fd).ok_or_else(io::Error::last_os_error)?;
+    unsafe {
+        libc::cfmakeraw(&mut termios);
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn get_size(fd: RawFd) -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } != 0 {
+        return None;
+    }
+    Some((size.ws_row, size.ws_col))
+}
+
+fn set_size(fd: RawFd, rows: u16, cols: u16) {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    unsafe {
+        libc::ioctl(fd, libc::TIOCSWINSZ, &size);
+    }
+}
@@ -1,7 +1,30 @@
-use clap::{Parser, Subcommand};
-use std::{error::Error, path::PathBuf};
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
+use std::{
+    env,
+    error::Error,
+    fs,
+    path::PathBuf,
+    process::{exit, Command},
+    time::Duration,
+};
 
-use contain::{config::Config, run::run_vm};
+use contain::{
+    attach::attach,
+    config::{
+        filesystem::{Disk, Share},
+        load::{FileFormat, LoadOptions},
+        Config,
+    },
+    control::{control, Action},
+    hotplug::{self, DeviceKind, Hotplugged},
+    instance::{self, InstanceInfo, Status},
+    library::{self, LibraryError},
+    logs,
+    run::{self, resolve, run_vm, Resolved, RunOptions},
+    snapshot,
+    stop,
+};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -14,39 +37,516 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Start {
-        config: PathBuf,
-        #[arg(short = 'c',
-          value_names = ["KEY", "VALUE"],
-          num_args = 2,
-          action = clap::ArgAction::Append,
-          help = "Override a configuration entry")]
-        overrides: Vec<String>,
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[arg(long, help = "Print what would be run instead of starting the vm")]
+        dry_run: bool,
+        #[arg(long, value_name = "SECONDS", help = "Time the guest gets to power off before it is stopped")]
+        grace_period: Option<u64>,
+        #[arg(long, value_name = "SNAPSHOT", help = "Resume from a snapshot name of this vm or a snapshot dir")]
+        restore: Option<String>,
+        #[arg(long, help = "Run the vm in the background and return once it is running")]
+        detach: bool,
+        #[arg(long, hide = true, value_name = "ID")]
+        detached_runner: Option<String>,
+    },
+    #[command(about = "Check a config for problems without starting it")]
+    Validate {
+        #[command(flatten)]
+        config: ConfigArgs,
+    },
+    #[command(about = "Print the JSON Schema of the config format")]
+    Schema,
+    #[command(about = "Inspect configs")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    #[command(about = "List the vm configs in the library")]
+    ListConfigs,
+    #[command(about = "Print the config file of a vm in the library")]
+    Show { name: String },
+    #[command(about = "Edit the config file of a vm in the library, creating it if needed")]
+    Edit {
+        name: String,
+        #[arg(long, default_value_t, help = "Format of the config file if it is created")]
+        format: FileFormat,
+    },
+    #[command(about = "List running vms and stale runtime dirs")]
+    Ps {
+        #[arg(long, help = "Print as JSON")]
+        json: bool,
+    },
+    #[command(about = "Shut a running vm down and wait for its runner to clean up")]
+    Stop {
+        #[arg(help = "Id, id prefix or name of a running vm")]
+        vm: String,
+        #[arg(long, value_name = "SECONDS", default_value_t = 60, help = "Time to wait for the runner to finish")]
+        timeout: u64,
+    },
+    #[command(about = "Kill an unresponsive vm and its helpers and remove its runtime dir")]
+    Kill {
+        #[arg(help = "Id, id prefix or name of a vm")]
+        vm: String,
+    },
+    #[command(about = "Attach the terminal to the console of a vm started with the socket sink")]
+    Attach {
+        #[arg(help = "Id, id prefix or name of a running vm")]
+        vm: String,
+        #[arg(long, help = "Attach to the serial port instead of the virtio console")]
+        serial: bool,
+        #[arg(long, help = "Only watch the console output")]
+        read_only: bool,
+    },
+    #[command(about = "Pause a running vm")]
+    Pause {
+        #[arg(help = "Id, id prefix or name of a running vm")]
+        vm: String,
+    },
+    #[command(about = "Resume a paused vm")]
+    Resume {
+        #[arg(help = "Id, id prefix or name of a running vm")]
+        vm: String,
+    },
+    #[command(about = "Reboot a running vm")]
+    Reboot {
+        #[arg(help = "Id, id prefix or name of a running vm")]
+        vm: String,
+    },
+    #[command(about = "Ask the guest of a running vm to power off")]
+    Poweroff {
+        #[arg(help = "Id, id prefix or name of a running vm")]
+        vm: String,
+    },
+    #[command(about = "Save the memory and device state of a running named vm")]
+    Snapshot {
+        #[arg(help = "Id, id prefix or name of a running vm")]
+        vm: String,
+        #[arg(long, default_value = "latest", help = "Name of the snapshot, an existing one is replaced")]
+        name: String,
+    },
+    #[command(about = "Attach a disk to a running vm, creating it if needed")]
+    AttachDisk {
+        #[arg(help = "Id, id prefix or name of a running vm")]
+        vm: String,
+        #[arg(value_name = "TAG:SIZE", help = "Disk to attach, size in MiB or suffixed with G")]
+        disk: Disk,
+        #[arg(long, help = "Path of the disk image instead of the vm's data dir")]
+        source: Option<PathBuf>,
+        #[arg(long, help = "Attach the disk read-only")]
+        readonly: bool,
+    },
+    #[command(about = "Share a directory with a running vm")]
+    AttachShare {
+        #[arg(help = "Id, id prefix or name of a running vm")]
+        vm: String,
+        #[arg(value_name = "SOURCE:TAG[:ro]", help = "Directory to share")]
+        share: Share,
+    },
+    #[command(about = "Attach an extra network interface to a running vm")]
+    AttachNet {
+        #[arg(help = "Id, id prefix or name of a running vm")]
+        vm: String,
+    },
+    #[command(about = "Detach a device from a running vm")]
+    Detach {
+        #[arg(help = "Id, id prefix or name of a running vm")]
+        vm: String,
+        #[arg(help = "Id of the device as printed when it was attached")]
+        device: String,
+    },
+    #[command(about = "Print the console, serial and support process output of a vm")]
+    Logs {
+        #[arg(help = "Id of a running vm or name of a vm with persistent logs")]
+        vm: String,
+        #[arg(
+            long,
+            help = "Only print this log, like console, serial, virtiofs-<tag> or gpu"
+        )]
+        helper: Option<String>,
+        #[arg(
+            short,
+            long,
+            help = "Keep printing new output of the log, the console unless --helper is given"
+        )]
+        follow: bool,
+        #[arg(
+            long,
+            help = "Only print console and serial output since a duration like 10m, 2h or 1d ago or a UTC date like 2024-01-31T12:00"
+        )]
+        since: Option<String>,
+        #[arg(
+            long,
+            allow_negative_numbers = true,
+            help = "Print console and serial output of this boot, negative numbers count back from the latest"
+        )]
+        boot: Option<i64>,
     },
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
-    let cli = Cli::parse();
+#[derive(Subcommand)]
+enum ConfigCommands {
+    #[command(about = "Print the effective config after defaults, extends and overrides")]
+    Show {
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[arg(long, help = "Print as JSON instead of YAML")]
+        json: bool,
+    },
+}
 
-    match cli.command {
-        Commands::Start { config, overrides } => {
-            let mut builder = config::Config::builder().add_source(config::File::from(config));
+#[derive(Serialize)]
+struct EffectiveConfig {
+    config: Config,
+    resolved: Resolved,
+}
+
+#[derive(Serialize)]
+struct PsEntry {
+    #[serde(flatten)]
+    info: InstanceInfo,
+    dir: PathBuf,
+    status: Status,
+    tap_devices: Vec<String>,
+}
+
+#[derive(Args)]
+struct ConfigArgs {
+    #[arg(help = "Path to a config file or name of a vm in the library")]
+    config: String,
+    #[arg(short = 'c',
+      value_names = ["KEY", "VALUE"],
+      num_args = 2,
+      action = clap::ArgAction::Append,
+      help = "Override a configuration entry, lists can be indexed with [N] and appended to with [+]")]
+    overrides: Vec<String>,
+    #[arg(long = "share", value_name = "SOURCE:TAG[:ro]", help = "Add a shared directory")]
+    shares: Vec<Share>,
+    #[arg(long = "disk", value_name = "TAG:SIZE", help = "Add a disk, size in MiB or suffixed with G")]
+    disks: Vec<Disk>,
+    #[arg(long, help = "Config file format, guessed from the extension by default")]
+    format: Option<FileFormat>,
+    #[arg(long, help = "Reject unknown configuration entries")]
+    strict: bool,
+}
+
+impl ConfigArgs {
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![self.config.clone()];
+        for pair in self.overrides.chunks_exact(2) {
+            args.extend(["-c".to_string(), pair[0].clone(), pair[1].clone()]);
+        }
+        for share in self.shares.iter() {
+            args.extend(["--share".to_string(), share.to_string()]);
+        }
+        for disk in self.disks.iter() {
+            args.extend(["--disk".to_string(), disk.to_string()]);
+        }
+        if let Some(format) = self.format {
+            args.extend(["--format".to_string(), format.to_string()]);
+        }
+        if self.strict {
+            args.push("--strict".to_string());
+        }
+        args
+    }
 
-            for (key, value) in overrides
+    fn path(&self) -> Option<PathBuf> {
+        let path = library::locate(&self.config).ok()?;
+        Some(fs::canonicalize(&path).unwrap_or(path))
+    }
+
+    fn load(self) -> Result<Config, LibraryError> {
+        let options = LoadOptions {
+            format: self.format,
+            overrides: self
+                .overrides
                 .chunks_exact(2)
                 .map(|p| (p[0].clone(), p[1].clone()))
-            {
-                builder = builder.set_override(key, value).expect("this is a bug");
-            }
+                .collect(),
+            shares: self.shares,
+            disks: self.disks,
+            strict: self.strict,
+        };
+        library::load_path_or_name(&self.config, &options)
+    }
+}
 
-            let config: Config = builder
-                .build()
-                .unwrap()
-                .try_deserialize()
-                .expect("issue with config");
-            run_vm(config).await?;
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run(Cli::parse()).await {
+        eprintln!("error: {}", e);
+        let mut source = e.source();
+        while let Some(e) = source {
+            eprintln!("  caused by: {}", e);
+            source = e.source();
+        }
+        exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match cli.command {
+        Commands::Start {
+            mut config,
+            dry_run,
+            grace_period,
+            restore,
+            detach,
+            detached_runner,
+        } => {
+            if let Some(grace_period) = grace_period {
+                config.overrides.push("shutdown.grace_period".to_string());
+                config.overrides.push(grace_period.to_string());
+            }
+            let mut runner_args = config.args();
+            if let Some(restore) = restore.as_ref() {
+                runner_args.extend(["--restore".to_string(), restore.clone()]);
+            }
+            let options = RunOptions {
+                restore,
+                config_path: config.path(),
+                detached: detach || detached_runner.is_some(),
+                id: detached_runner,
+            };
+            let config = config.load()?;
+            if dry_run {
+                print!("{}", run::dry_run(&config, &options)?);
+            } else if detach {
+                let exe = env::current_exe()?;
+                let plan = run::run_detached(&config, &options, |id| {
+                    let mut command = Command::new(exe);
+                    command
+                        .arg("start")
+                        .args(runner_args)
+                        .arg("--detached-runner")
+                        .arg(id);
+                    command
+                })
+                .await?;
+                println!("{}", plan.id);
+            } else {
+                run_vm(config, &options).await?;
+            }
+        }
+        Commands::Validate { config } => {
+            let config = config.load()?;
+            let report = config.validate();
+            if !report.is_ok() {
+                eprint!("{}", report);
+                eprintln!("found {} problem(s)", report.problems.len());
+                exit(1);
+            }
+        }
+        Commands::Schema => {
+            println!("{}", serde_json::to_string_pretty(&Config::schema())?);
+        }
+        Commands::Config {
+            command: ConfigCommands::Show { config, json },
+        } => {
+            let config = config.load()?;
+            let effective = EffectiveConfig {
+                resolved: resolve(&config)?,
+                config,
+            };
+            if json {
+                println!("{}", serde_json::to_string_pretty(&effective)?);
+            } else {
+                print!("{}", serde_norway::to_string(&effective)?);
+            }
+        }
+        Commands::ListConfigs => {
+            for entry in library::list()? {
+                println!("{}\t{}", entry.name, entry.path.display());
+            }
+        }
+        Commands::Show { name } => {
+            print!("{}", fs::read_to_string(library::find(&name)?)?);
+        }
+        Commands::Edit { name, format } => {
+            let path = match library::find(&name) {
+                Ok(path) => path,
+                Err(LibraryError::NotFound { .. }) => library::create(&name, format)?,
+                Err(e) => return Err(e.into()),
+            };
+            let editor = env::var("VISUAL")
+                .or_else(|_| env::var("EDITOR"))
+                .unwrap_or("vi".to_string());
+            // Editors are often set with arguments, like "code --wait", so
+            // let the shell split them the way it would when run by hand.
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(format!("{} \"$1\"", editor))
+                .arg("sh")
+                .arg(&path)
+                .status()?;
+            if !status.success() {
+                return Err(LibraryError::EditorFailed { editor, status }.into());
+            }
+            library::load(&name, &LoadOptions::default())?;
+        }
+        Commands::Ps { json } => {
+            let mut entries = vec![];
+            for instance in instance::list()? {
+                let mut tap_devices: Vec<String> =
+                    instance.info.tap_device.iter().cloned().collect();
+                if let Ok(hotplugged) = Hotplugged::read(&instance.dir) {
+                    for device in hotplugged.devices {
+                        if let DeviceKind::Net { tap_device } = device.kind {
+                            tap_devices.push(tap_device);
+                        }
+                    }
+                }
+                entries.push(PsEntry {
+                    status: instance.status(),
+                    info: instance.info,
+                    dir: instance.dir,
+                    tap_devices,
+                });
+            }
+            if json {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else {
+                println!(
+                    "{:<12}  {:<16}  {:>8}  {:<8}  {:>8}  {:<16}  PTY",
+                    "ID", "NAME", "PID", "STATUS", "UP", "TAP"
+                );
+                let now = instance::unix_time();
+                for entry in entries.iter() {
+                    println!(
+                        "{:<12}  {:<16}  {:>8}  {:<8}  {:>8}  {:<16}  {}",
+                        &entry.info.id[..entry.info.id.len().min(12)],
+                        entry.info.name.as_deref().unwrap_or("-"),
+                        entry.info.pid,
+                        entry.status,
+                        match entry.info.started {
+                            0 => "-".to_string(),
+                            started => format_uptime(now.saturating_sub(started)),
+                        },
+                        if entry.tap_devices.is_empty() {
+                            "-".to_string()
+                        } else {
+                            entry.tap_devices.join(",")
+                        },
+                        if entry.info.ptys.is_empty() {
+                            "-".to_string()
+                        } else {
+                            entry
+                                .info
+                                .ptys
+                                .iter()
+                                .map(|pty| format!("{}={}", pty.device, pty.path.display()))
+                                .collect::<Vec<_>>()
+                                .join(",")
+                        }
+                    );
+                }
+            }
+        }
+        Commands::Stop { vm, timeout } => {
+            stop::stop(&vm, Duration::from_secs(timeout)).await?;
+        }
+        Commands::Kill { vm } => {
+            stop::kill(&vm).await?;
+        }
+        Commands::Attach {
+            vm,
+            serial,
+            read_only,
+        } => {
+            attach(&vm, serial, read_only).await?;
+        }
+        Commands::Pause { vm } => {
+            control(&vm, Action::Pause).await?;
+        }
+        Commands::Resume { vm } => {
+            control(&vm, Action::Resume).await?;
+        }
+        Commands::Reboot { vm } => {
+            control(&vm, Action::Reboot).await?;
+        }
+        Commands::Poweroff { vm } => {
+            control(&vm, Action::PowerOff).await?;
+        }
+        Commands::Snapshot { vm, name } => {
+            let snapshot = snapshot::snapshot(&vm, &name).await?;
+            println!("{}", snapshot.dir.display());
+        }
+        Commands::AttachDisk {
+            vm,
+            mut disk,
+            source,
+            readonly,
+        } => {
+            disk.source = source;
+            println!("{}", hotplug::attach_disk(&vm, &disk, readonly).await?.id);
+        }
+        Commands::AttachShare { vm, share } => {
+            println!("{}", hotplug::attach_share(&vm, &share).await?.id);
+        }
+        Commands::AttachNet { vm } => {
+            println!("{}", hotplug::attach_net(&vm).await?.id);
+        }
+        Commands::Detach { vm, device } => {
+            hotplug::detach(&vm, &device).await?;
+        }
+        Commands::Logs {
+            vm,
+            helper,
+            follow,
+            since,
+            boot,
+        } => {
+            let since = since.map(|since| logs::parse_since(&since)).transpose()?;
+            let mut available = vec![];
+            for dir in logs::find_dirs(&vm, boot)? {
+                for helper in logs::helpers(&dir)? {
+                    if !available.iter().any(|(h, _)| *h == helper) {
+                        available.push((helper, dir.clone()));
+                    }
+                }
+            }
+            let selected = match (helper, follow) {
+                (Some(helper), _) => Some(helper),
+                (None, true) => Some(logs::CONSOLE_LOG.to_string()),
+                (None, false) => None,
+            };
+            match selected {
+                Some(helper) => {
+                    let Some((_, dir)) = available.iter().find(|(h, _)| *h == helper) else {
+                        return Err(logs::LogsError::UnknownHelper {
+                            helper,
+                            available: available.into_iter().map(|(h, _)| h).collect(),
+                        }
+                        .into());
+                    };
+                    if follow {
+                        logs::follow(dir, &helper, since.as_deref()).await?;
+                    } else {
+                        print!("{}", logs::read_since(dir, &helper, since.as_deref())?);
+                    }
+                }
+                None => {
+                    for (i, (helper, dir)) in available.iter().enumerate() {
+                        if i > 0 {
+                            println!();
+                        }
+                        println!("==> {} <==", helper);
+                        print!("{}", logs::read_since(dir, helper, since.as_deref())?);
+                    }
+                }
+            }
         }
     }
 
     Ok(())
 }
+
+fn format_uptime(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d{:02}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
@@ -1,22 +1,36 @@
-use http::{Method, Request, Response};
+use http::{Method, Request, Response, StatusCode};
 use http_body_util::{BodyExt, Full};
 use hyper::body::{Buf, Bytes, Incoming};
 use hyper_util::client::legacy::Client;
 use hyperlocal::{UnixClientExt, UnixConnector, Uri};
 use serde::{de::DeserializeOwned, Serialize};
+use std::path::Path;
 use thiserror::Error;
 
+use crate::config::filesystem::Share;
 use crate::daemon::{requests::*, DEFAULT_SOCKET_PATH};
+use crate::instance::HelperInfo;
+use crate::run::api::ShareHelperRequest;
 
-pub async fn request_tap_device(user: String) -> Result<String, RequestError> {
-    let body = NetTapCreateRequest { user };
-    let NetTapCreateResponse { name } = json_request("/api/net/tap", Method::POST, body).await?;
+pub async fn request_tap_device(user: String, name: Option<String>) -> Result<String, RequestError> {
+    let body = NetTapCreateRequest { user, name };
+    let NetTapCreateResponse { name } = json_request(Path::new(DEFAULT_SOCKET_PATH), "/api/net/tap", Method::POST, body).await?;
     Ok(name)
 }
 
-pub async fn delete_tap_device(name: String) -> Result<(), RequestError>{
-    let body = NetTapDeleteRequest { name };
-    json_call("/api/net/tap", Method::DELETE, body).await?;
+pub async fn delete_tap_device(name: String, keep_owner: bool) -> Result<(), RequestError>{
+    let body = NetTapDeleteRequest { name, keep_owner };
+    json_call(Path::new(DEFAULT_SOCKET_PATH), "/api/net/tap", Method::DELETE, body).await?;
+    Ok(())
+}
+
+pub async fn start_share_helper(runner: &Path, share: Share) -> Result<HelperInfo, RequestError> {
+    let body = ShareHelperRequest { share };
+    json_request(runner, "/api/helpers/share", Method::POST, body).await
+}
+
+pub async fn stop_helper(runner: &Path, name: &str) -> Result<(), RequestError> {
+    call(runner, &format!("/api/helpers/{}", name), Method::DELETE, Bytes::new()).await?;
     Ok(())
 }
 
@@ -30,30 +44,43 @@ pub enum RequestError {
     RequestFailed(#[from] hyper::Error),
     #[error("client error")]
     ClientError(#[from] hyper_util::client::legacy::Error),
+    #[error("request failed with status {status}{}", if .body.is_empty() { String::new() } else { format!(": {}", .body) })]
+    Status { status: StatusCode, body: String },
 }
 
-async fn json_call<B: Serialize>(route: &str, method: Method, body: B) -> Result<Response<Incoming>, RequestError> {
-    let url = Uri::new(DEFAULT_SOCKET_PATH, &route);
+pub(crate) async fn call(socket: &Path, route: &str, method: Method, body: Bytes) -> Result<Response<Incoming>, RequestError> {
+    let url = Uri::new(socket, route);
     let client: Client<UnixConnector, Full<Bytes>> = Client::unix();
 
-    let json: String = serde_json::to_string(&body)?;
-    let bytes = Full::new(Bytes::from_owner(json));
-
     let req = Request::builder()
         .uri(url)
         .method(method)
         .header("Content-Type", "application/json")
-        .body(bytes)?;
+        .body(Full::new(body))?;
 
     let res = client.request(req).await?;
+    if !res.status().is_success() {
+        let status = res.status();
+        let body = res.into_body().collect().await?.to_bytes();
+        let body = String::from_utf8_lossy(&body).trim().to_string();
+        return Err(RequestError::Status { status, body });
+    }
     Ok(res)
 }
 
-async fn json_request<B: Serialize, R: DeserializeOwned>(route: &str, method: Method, body: B) -> Result<R, RequestError> {
-    let res = json_call(route, method, body).await?;
+pub(crate) async fn json_call<B: Serialize>(socket: &Path, route: &str, method: Method, body: B) -> Result<Response<Incoming>, RequestError> {
+    let json: String = serde_json::to_string(&body)?;
+    call(socket, route, method, Bytes::from_owner(json)).await
+}
 
+pub(crate) async fn read_json<R: DeserializeOwned>(res: Response<Incoming>) -> Result<R, RequestError> {
     let reader = res.into_body().collect().await?.aggregate().reader();
 
     let json = serde_json::from_reader::<_, R>(reader)?;
     Ok(json)
 }
+
+async fn json_request<B: Serialize, R: DeserializeOwned>(socket: &Path, route: &str, method: Method, body: B) -> Result<R, RequestError> {
+    let res = json_call(socket, route, method, body).await?;
+    read_json(res).await
+}
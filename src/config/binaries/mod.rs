@@ -0,0 +1,112 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::config::load::read_value;
+use crate::config::ConfigError;
+
+static DEFAULTS_FILE_STEM: &str = "binaries";
+
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
+#[serde(default)]
+#[schemars(deny_unknown_fields)]
+pub struct Binaries {
+    pub cloud_hypervisor: Option<PathBuf>,
+    pub virtiofsd: Option<PathBuf>,
+    pub crosvm: Option<PathBuf>,
+}
+
+impl Binaries {
+    pub fn user_defaults_path() -> Option<PathBuf> {
+        let dir = dirs::config_dir()?.join("contain");
+        ["json", "toml", "yaml", "yml"]
+            .iter()
+            .map(|extension| dir.join(format!("{}.{}", DEFAULTS_FILE_STEM, extension)))
+            .find(|path| path.is_file())
+    }
+
+    pub fn user_defaults() -> Result<Self, ConfigError> {
+        match Self::user_defaults_path() {
+            Some(path) => Self::read(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    // Relative paths are resolved against the directory of the file, while
+    // bare names are still looked up in PATH.
+    pub fn read(path: &Path) -> Result<Self, ConfigError> {
+        let value = read_value(path, None)?;
+        let binaries: Self =
+            serde_json::from_value(value).map_err(|source| ConfigError::Invalid {
+                key: format!("binaries in {:?}", path),
+                source: Box::new(source),
+            })?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let resolve = |binary: Option<PathBuf>| {
+            binary.map(|binary| {
+                if binary.components().count() > 1 {
+                    dir.join(binary)
+                } else {
+                    binary
+                }
+            })
+        };
+        Ok(Self {
+            cloud_hypervisor: resolve(binaries.cloud_hypervisor),
+            virtiofsd: resolve(binaries.virtiofsd),
+            crosvm: resolve(binaries.crosvm),
+        })
+    }
+
+    pub fn or(&self, defaults: &Self) -> Self {
+        Self {
+            cloud_hypervisor: self
+                .cloud_hypervisor
+                .clone()
+                .or(defaults.cloud_hypervisor.clone()),
+            virtiofsd: self.virtiofsd.clone().or(defaults.virtiofsd.clone()),
+            crosvm: self.crosvm.clone().or(defaults.crosvm.clone()),
+        }
+    }
+
+    pub fn cloud_hypervisor(&self) -> PathBuf {
+        self.cloud_hypervisor
+            .clone()
+            .unwrap_or("cloud-hypervisor".into())
+    }
+
+    pub fn virtiofsd(&self) -> PathBuf {
+        self.virtiofsd.clone().unwrap_or("virtiofsd".into())
+    }
+
+    pub fn crosvm(&self) -> PathBuf {
+        self.crosvm.clone().unwrap_or("crosvm".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn relative_paths_are_resolved_against_the_defaults_file() {
+        let dir = std::env::temp_dir().join(format!("contain-binaries-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("binaries.json");
+        fs::write(
+            &path,
+            r#"{ "cloud_hypervisor": "bin/cloud-hypervisor", "virtiofsd": "virtiofsd-custom", "crosvm": "/usr/bin/crosvm" }"#,
+        )
+        .unwrap();
+
+        let binaries = Binaries::read(&path).unwrap();
+        assert_eq!(
+            binaries.cloud_hypervisor(),
+            dir.join("bin/cloud-hypervisor")
+        );
+        assert_eq!(binaries.virtiofsd(), PathBuf::from("virtiofsd-custom"));
+        assert_eq!(binaries.crosvm(), PathBuf::from("/usr/bin/crosvm"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,48 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::path::PathBuf;
 
-#[derive(Default, Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
 #[serde(default)]
+#[schemars(deny_unknown_fields)]
 pub struct Console {
-    pub mode: Mode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<Mode>,
+    pub console: Device,
+    pub serial: Device,
+    pub cmdline: bool,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
+#[serde(default)]
+#[schemars(deny_unknown_fields)]
+pub struct Device {
+    pub sink: Sink,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Sink {
+    #[default]
+    #[serde(rename = "off")]
+    Off,
+    #[serde(rename = "tty")]
+    Tty,
+    #[serde(rename = "file")]
+    File,
+    #[serde(rename = "socket")]
+    Socket,
+    #[serde(rename = "pty")]
+    Pty,
+    #[serde(rename = "log")]
+    Log,
+}
+
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
 pub enum Mode {
+    #[default]
     #[serde(rename = "off")]
     Off,
     #[serde(rename = "log")]
@@ -16,10 +51,39 @@ pub enum Mode {
     On,
     #[serde(rename = "serial")]
     Serial,
+    #[serde(rename = "socket")]
+    Socket,
+}
+
+impl Console {
+    pub fn devices(&self) -> (Device, Device) {
+        let (console, serial) = match self.mode {
+            None => return (self.console.clone(), self.serial.clone()),
+            Some(Mode::Off) => (Sink::Off, Sink::Off),
+            Some(Mode::Log) => (Sink::Log, Sink::Off),
+            Some(Mode::On) => (Sink::Tty, Sink::Off),
+            Some(Mode::Serial) => (Sink::Off, Sink::Tty),
+            Some(Mode::Socket) => (Sink::Socket, Sink::Off),
+        };
+        (Device::sink(console), Device::sink(serial))
+    }
+}
+
+impl Device {
+    pub fn sink(sink: Sink) -> Self {
+        Self { sink, file: None }
+    }
 }
 
-impl Default for Mode {
-    fn default() -> Self {
-        Self::Off
+impl Display for Sink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Off => "off",
+            Self::Tty => "tty",
+            Self::File => "file",
+            Self::Socket => "socket",
+            Self::Pty => "pty",
+            Self::Log => "log",
+        })
     }
 }
@@ -1,16 +1,24 @@
-use std::{fmt::Display, path::PathBuf};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-#[derive(Default, Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
 #[serde(default)]
+#[schemars(deny_unknown_fields)]
 pub struct Filesystem {
     pub shares: Vec<Share>,
     pub disks: Vec<Disk>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
 #[serde(default)]
+#[schemars(deny_unknown_fields)]
 pub struct Share {
     pub source: PathBuf,
     pub tag: String,
@@ -29,7 +37,41 @@ impl Default for Share {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+impl FromStr for Share {
+    type Err = ParseShareError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rest, write) = match s.strip_suffix(":ro") {
+            Some(rest) => (rest, false),
+            None => (s.strip_suffix(":rw").unwrap_or(s), true),
+        };
+        let (source, tag) = rest
+            .rsplit_once(':')
+            .filter(|(source, tag)| !source.is_empty() && !tag.is_empty())
+            .ok_or_else(|| ParseShareError(s.to_string()))?;
+        Ok(Self {
+            source: source.into(),
+            tag: tag.to_string(),
+            write,
+            ..Default::default()
+        })
+    }
+}
+
+impl Display for Share {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.source.display(), self.tag)?;
+        if !self.write {
+            f.write_str(":ro")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("\"{0}\" is not a valid share, expected SOURCE:TAG[:ro]")]
+pub struct ParseShareError(String);
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
 pub enum InodeFileHandles {
     #[serde(rename = "never")]
     Never,
@@ -49,8 +91,9 @@ impl Display for InodeFileHandles {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
 #[serde(default)]
+#[schemars(deny_unknown_fields)]
 pub struct Disk {
     pub source: Option<PathBuf>,
     pub tag: String,
@@ -73,7 +116,48 @@ impl Default for Disk {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+impl Disk {
+    pub fn resolve_path(&self, name: &Option<String>, data_dir: &Path) -> Option<PathBuf> {
+        match (self.source.clone(), name) {
+            (Some(p), _) => Some(p),
+            (None, Some(n)) => Some(data_dir.join(n).join(format!("{}.{}", self.tag, self.format))),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Disk {
+    type Err = ParseDiskError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseDiskError(s.to_string());
+        let (tag, size) = s.split_once(':').ok_or_else(err)?;
+        let size = match size.strip_suffix(['G', 'g']) {
+            Some(gib) => gib.parse::<u64>().ok().and_then(|s| s.checked_mul(1024)),
+            None => size.strip_suffix(['M', 'm']).unwrap_or(size).parse::<u64>().ok(),
+        }
+        .ok_or_else(err)?;
+        if tag.is_empty() {
+            return Err(err());
+        }
+        Ok(Self {
+            tag: tag.to_string(),
+            size,
+            ..Default::default()
+        })
+    }
+}
+
+impl Display for Disk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.tag, self.size)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("\"{0}\" is not a valid disk, expected TAG:SIZE with the size in MiB or suffixed with G")]
+pub struct ParseDiskError(String);
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
 pub enum Format {
     #[serde(rename = "qcow2")]
     Qcow2,
@@ -89,3 +173,36 @@ impl Display for Format {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disk_sizes_parse_in_mib() {
+        assert_eq!("data:512".parse::<Disk>().unwrap().size, 512);
+        assert_eq!("data:512M".parse::<Disk>().unwrap().size, 512);
+        assert_eq!("data:2g".parse::<Disk>().unwrap().size, 2048);
+    }
+
+    #[test]
+    fn oversized_disks_are_rejected() {
+        let max = format!("data:{}G", u64::MAX);
+        assert!(max.parse::<Disk>().is_err());
+        let max = format!("data:{}G", u64::MAX / 1024 + 1);
+        assert!(max.parse::<Disk>().is_err());
+        assert!(format!("data:{}G", u64::MAX / 1024).parse::<Disk>().is_ok());
+    }
+
+    #[test]
+    fn cli_shares_and_disks_round_trip() {
+        for share in ["/srv/data:data", "/srv/a:b:ro", "/srv/home:home"] {
+            assert_eq!(share.parse::<Share>().unwrap().to_string(), share);
+        }
+        assert_eq!(
+            "/srv/home:home:rw".parse::<Share>().unwrap().to_string(),
+            "/srv/home:home"
+        );
+        assert_eq!("data:2G".parse::<Disk>().unwrap().to_string(), "data:2048");
+    }
+}
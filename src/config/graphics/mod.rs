@@ -1,7 +1,9 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Default, Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
 #[serde(default)]
+#[schemars(deny_unknown_fields)]
 pub struct Graphics {
     pub virtio_gpu: bool,
 }
@@ -0,0 +1,18 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
+#[serde(default)]
+#[schemars(deny_unknown_fields)]
+pub struct Hypervisor {
+    pub boot: Boot,
+}
+
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
+pub enum Boot {
+    #[default]
+    #[serde(rename = "api")]
+    Api,
+    #[serde(rename = "argv")]
+    Argv,
+}
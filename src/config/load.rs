@@ -0,0 +1,312 @@
+use serde_json::Value;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::config::filesystem::{Disk, Share};
+use crate::config::overrides::{OverrideError, Overrider};
+use crate::config::{deserialize, Config, ConfigError};
+
+#[derive(Default, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FileFormat {
+    #[default]
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl FileFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    pub fn parse(&self, s: &str) -> Result<Value, ParseError> {
+        match self {
+            Self::Json => serde_json::from_str(s).map_err(|e| ParseError {
+                location: (e.line() > 0).then(|| Location {
+                    line: e.line(),
+                    column: e.column(),
+                }),
+                source: e.into(),
+            }),
+            Self::Toml => toml::from_str(s).map_err(|e| ParseError {
+                location: e.span().map(|span| Location::from_offset(s, span.start)),
+                source: e.into(),
+            }),
+            Self::Yaml => serde_norway::from_str(s).map_err(|e| ParseError {
+                location: e.location().map(|l| Location {
+                    line: l.line(),
+                    column: l.column(),
+                }),
+                source: e.into(),
+            }),
+        }
+    }
+}
+
+impl Display for FileFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Json => "json",
+            Self::Toml => "toml",
+            Self::Yaml => "yaml",
+        })
+    }
+}
+
+impl FromStr for FileFormat {
+    type Err = UnknownFileFormat;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            _ => Err(UnknownFileFormat(s.to_string())),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("unknown format \"{0}\", should be one of json, toml or yaml")]
+pub struct UnknownFileFormat(String);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    fn from_offset(s: &str, offset: usize) -> Self {
+        let before = &s[..offset.min(s.len())];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("{source}")]
+pub struct ParseError {
+    pub location: Option<Location>,
+    pub source: Box<dyn std::error::Error + Send + Sync>,
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct LoadOptions {
+    pub format: Option<FileFormat>,
+    pub overrides: Vec<(String, String)>,
+    pub shares: Vec<Share>,
+    pub disks: Vec<Disk>,
+    pub strict: bool,
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>, options: &LoadOptions) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let mut value = resolve_value(path, options.format, &mut vec![])?;
+
+        let overrider = Overrider::new();
+        let mut overridden = vec![];
+        for (key, raw) in options.overrides.iter() {
+            let entry = overrider
+                .apply(&mut value, key, raw)
+                .map_err(|e| ConfigError::Override(key.clone(), e))?;
+            overridden.push((key, entry));
+        }
+
+        let (mut config, unknown) = deserialize(value)?;
+        for path in unknown.iter() {
+            if let Some((key, _)) = overridden
+                .iter()
+                .find(|(_, entry)| path.starts_with(&format!("{}.", entry)))
+            {
+                return Err(ConfigError::Override(
+                    key.to_string(),
+                    OverrideError::UnknownEntry(path.clone()),
+                ));
+            }
+        }
+        if options.strict && !unknown.is_empty() {
+            return Err(ConfigError::UnknownFields(unknown));
+        }
+
+        config
+            .filesystem
+            .shares
+            .extend(options.shares.iter().cloned());
+        config
+            .filesystem
+            .disks
+            .extend(options.disks.iter().cloned());
+        Ok(config)
+    }
+}
+
+pub fn read_value(path: &Path, format: Option<FileFormat>) -> Result<Value, ConfigError> {
+    let format = format
+        .or_else(|| FileFormat::from_path(path))
+        .ok_or_else(|| ConfigError::UnknownFormat(path.to_path_buf()))?;
+    let s = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+    format.parse(&s).map_err(|e| ConfigError::Parse {
+        path: path.to_path_buf(),
+        format,
+        location: e.location,
+        source: e.source,
+    })
+}
+
+fn resolve_value(
+    path: &Path,
+    format: Option<FileFormat>,
+    chain: &mut Vec<PathBuf>,
+) -> Result<Value, ConfigError> {
+    let canonical = fs::canonicalize(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+    if chain.contains(&canonical) {
+        chain.push(canonical);
+        return Err(ConfigError::ExtendsCycle(chain.clone()));
+    }
+
+    let mut value = read_value(path, format)?;
+    let extends = match value.as_object_mut().and_then(|o| o.remove("extends")) {
+        Some(extends) => {
+            serde_json::from_value::<Vec<PathBuf>>(extends).map_err(|e| ConfigError::Invalid {
+                key: "extends".to_string(),
+                source: Box::new(e),
+            })?
+        }
+        None => vec![],
+    };
+
+    chain.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut merged = Value::Object(Default::default());
+    for base in extends {
+        let base = dir.join(base);
+        let base_format = FileFormat::from_path(&base).or(format);
+        let base_value = resolve_value(&base, base_format, chain)
+            .map_err(|e| ConfigError::Extends(base, Box::new(e)))?;
+        merge(&mut merged, base_value);
+    }
+    chain.pop();
+
+    merge(&mut merged, value);
+    Ok(merged)
+}
+
+pub fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(overlay)) => {
+            for value in overlay {
+                if let Some(existing) = list_key(&value)
+                    .and_then(|key| base.iter_mut().find(|b| list_key(b) == Some(key)))
+                {
+                    merge(existing, value);
+                } else if !base.contains(&value) {
+                    base.push(value);
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+// Shares and disks are identified by their tag, so an overlay entry with the
+// same tag as a base entry updates it instead of being added alongside it.
+fn list_key(value: &Value) -> Option<&str> {
+    value.get("tag").and_then(Value::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn tagged_list_entries_are_merged_by_tag() {
+        let mut base = json!({ "filesystem": { "shares": [
+            { "source": "/nix/store", "tag": "store", "write": false },
+            { "source": "/home", "tag": "home" },
+        ] } });
+        merge(
+            &mut base,
+            json!({ "filesystem": { "shares": [
+                { "source": "/srv/home", "tag": "home" },
+                { "source": "/tmp", "tag": "tmp" },
+            ] } }),
+        );
+        assert_eq!(
+            base,
+            json!({ "filesystem": { "shares": [
+                { "source": "/nix/store", "tag": "store", "write": false },
+                { "source": "/srv/home", "tag": "home" },
+                { "source": "/tmp", "tag": "tmp" },
+            ] } })
+        );
+    }
+
+    #[test]
+    fn untagged_list_entries_are_deduplicated() {
+        let mut base = json!(["a", "b"]);
+        merge(&mut base, json!(["b", "c"]));
+        assert_eq!(base, json!(["a", "b", "c"]));
+    }
+
+    #[test]
+    fn extends_is_resolved_before_deserializing() {
+        let dir = std::env::temp_dir().join(format!("contain-extends-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("base.json"), r#"{ "cmdline": "console=hvc0" }"#).unwrap();
+        fs::write(
+            dir.join("vm.json"),
+            r#"{ "extends": ["base.json"], "name": "vm" }"#,
+        )
+        .unwrap();
+
+        let options = LoadOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let config = Config::load(dir.join("vm.json"), &options).unwrap();
+        assert_eq!(config.name.as_deref(), Some("vm"));
+        assert_eq!(config.cmdline, "console=hvc0");
+        assert!(Config::schema()
+            .get("properties")
+            .and_then(|p| p.get("extends"))
+            .is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn yaml_errors_carry_their_location() {
+        let value = FileFormat::Yaml.parse("name: vm\ncpu:\n  cores: 2\n").unwrap();
+        assert_eq!(value, json!({ "name": "vm", "cpu": { "cores": 2 } }));
+        let e = FileFormat::Yaml.parse("name: vm\n  bad: [\n").unwrap_err();
+        assert_eq!(e.location, Some(Location { line: 2, column: 6 }));
+    }
+}
@@ -0,0 +1,23 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
+#[serde(default)]
+#[schemars(deny_unknown_fields)]
+pub struct Logs {
+    pub persistent: bool,
+    pub max_size: u64,
+    pub rotations: u64,
+    pub boots: u64,
+}
+
+impl Default for Logs {
+    fn default() -> Self {
+        Self {
+            persistent: true,
+            max_size: 1024,
+            rotations: 3,
+            boots: 10,
+        }
+    }
+}
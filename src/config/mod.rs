@@ -1,9 +1,11 @@
-use serde::{Deserialize, Serialize};
-use std::{fs, io, path::PathBuf};
+use schemars::{JsonSchema, Schema, SchemaGenerator};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{io, path::PathBuf};
 use thiserror::Error;
 
-#[derive(Default, Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
 #[serde(default)]
+#[schemars(deny_unknown_fields)]
 pub struct Config {
     pub name: Option<String>,
     pub kernel_path: PathBuf,
@@ -15,6 +17,11 @@ pub struct Config {
     pub network: network::Network,
     pub graphics: graphics::Graphics,
     pub console: console::Console,
+    pub binaries: binaries::Binaries,
+    pub support: support::Support,
+    pub logs: logs::Logs,
+    pub shutdown: shutdown::Shutdown,
+    pub hypervisor: hypervisor::Hypervisor,
 }
 
 pub mod cpu;
@@ -29,18 +36,106 @@ pub mod graphics;
 
 pub mod console;
 
+pub mod binaries;
+
+pub mod support;
+
+pub mod logs;
+
+pub mod shutdown;
+
+pub mod hypervisor;
+
+pub mod validate;
+
+pub mod load;
+
+pub mod overrides;
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
-    #[error("unable to read config file")]
-    Io(#[from] io::Error),
-    #[error("unable to parse config file")]
-    Parse(#[from] serde_json::Error),
+    #[error("unable to read config file {0:?}")]
+    Io(PathBuf, #[source] io::Error),
+    #[error("unable to determine format of config file {0:?}, use a json, toml or yaml extension")]
+    UnknownFormat(PathBuf),
+    #[error(
+        "unable to parse {format} config file {path:?}{}",
+        location.map(|l| format!(" at {}", l)).unwrap_or_default()
+    )]
+    Parse {
+        path: PathBuf,
+        format: load::FileFormat,
+        location: Option<load::Location>,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("invalid config entry {key}")]
+    Invalid {
+        key: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("invalid override for {0}")]
+    Override(String, #[source] overrides::OverrideError),
+    #[error("unknown config entries: {}", .0.join(", "))]
+    UnknownFields(Vec<String>),
+    #[error("unable to load config {0:?} it extends")]
+    Extends(PathBuf, #[source] Box<ConfigError>),
+    #[error(
+        "config files extend each other in a cycle: {}",
+        .0.iter().map(|p| format!("{:?}", p)).collect::<Vec<_>>().join(" -> ")
+    )]
+    ExtendsCycle(Vec<PathBuf>),
+}
+
+impl Config {
+    // "extends" is resolved and removed by load() before the config is
+    // deserialized, so it is only part of the schema, not of Config.
+    pub fn schema() -> Schema {
+        let mut generator = SchemaGenerator::default();
+        let mut schema = generator.root_schema_for::<Config>();
+        let extends = generator.subschema_for::<Vec<PathBuf>>();
+        if let Some(properties) = schema
+            .get_mut("properties")
+            .and_then(|p| p.as_object_mut())
+        {
+            properties.insert("extends".to_string(), extends.to_value());
+        }
+        schema
+    }
+
+    pub fn from_deserializer<'de, D>(deserializer: D, strict: bool) -> Result<Self, ConfigError>
+    where
+        D: Deserializer<'de>,
+        D::Error: Send + Sync + 'static,
+    {
+        let (config, unknown) = deserialize(deserializer)?;
+        if strict && !unknown.is_empty() {
+            return Err(ConfigError::UnknownFields(unknown));
+        }
+        Ok(config)
+    }
+}
+
+fn deserialize<'de, D>(deserializer: D) -> Result<(Config, Vec<String>), ConfigError>
+where
+    D: Deserializer<'de>,
+    D::Error: Send + Sync + 'static,
+{
+    let mut unknown = vec![];
+    let mut track = |path: serde_ignored::Path| unknown.push(path.to_string().replace("?.", ""));
+    let deserializer = serde_ignored::Deserializer::new(deserializer, &mut track);
+    let config =
+        serde_path_to_error::deserialize(deserializer).map_err(|e| ConfigError::Invalid {
+            key: e.path().to_string(),
+            source: Box::new(e.into_inner()),
+        })?;
+    Ok((config, unknown))
 }
 
 impl TryFrom<PathBuf> for Config {
     type Error = ConfigError;
     fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
-        let s = fs::read_to_string(path)?;
-        serde_json::from_str(&s).map_err(|e| ConfigError::Parse(e))
+        Self::load(path, &load::LoadOptions::default())
     }
 }
@@ -0,0 +1,335 @@
+use regex::Regex;
+use serde_json::{Map, Number, Value};
+use std::sync::LazyLock;
+use thiserror::Error;
+
+use crate::config::Config;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(i64),
+    Append,
+}
+
+#[derive(Error, Debug)]
+pub enum OverrideError {
+    #[error("\"{0}\" is not a valid key, expected something like filesystem.shares[0].tag")]
+    InvalidKey(String),
+    #[error("unknown key \"{key}\", expected one of {}", .expected.join(", "))]
+    UnknownKey { key: String, expected: Vec<String> },
+    #[error("\"{0}\" is not a list")]
+    NotAList(String),
+    #[error("index {index} is out of range for a list of length {len}")]
+    IndexOutOfRange { index: i64, len: usize },
+    #[error("\"{value}\" is not a valid {expected}")]
+    WrongType { value: String, expected: String },
+    #[error("\"{value}\" is not a valid value, expected one of {}", .expected.join(", "))]
+    InvalidEnumValue {
+        value: String,
+        expected: Vec<String>,
+    },
+    #[error("unknown entry \"{0}\" in override value")]
+    UnknownEntry(String),
+    #[error("extends is resolved before overrides are applied, so it cannot be overridden")]
+    Extends,
+    #[error("\"{0}\" is not valid json")]
+    InvalidJson(String, #[source] serde_json::Error),
+}
+
+pub(crate) struct Overrider {
+    schema: Value,
+}
+
+impl Overrider {
+    pub(crate) fn new() -> Self {
+        Self {
+            schema: Config::schema().to_value(),
+        }
+    }
+
+    pub(crate) fn apply(
+        &self,
+        value: &mut Value,
+        key: &str,
+        raw: &str,
+    ) -> Result<String, OverrideError> {
+        let segments = parse_key(key)?;
+        if matches!(segments.first(), Some(Segment::Key(key)) if key == "extends") {
+            return Err(OverrideError::Extends);
+        }
+
+        let mut node = self.resolve(&self.schema);
+        let mut nullable = false;
+        let mut current = value;
+        let mut path = vec![];
+
+        for segment in segments {
+            match segment {
+                Segment::Key(key) => {
+                    let Some(child) = self.child(node, &key) else {
+                        return Err(OverrideError::UnknownKey {
+                            expected: self.keys(node),
+                            key,
+                        });
+                    };
+                    nullable = self.nullable(child);
+                    node = self.resolve(child);
+                    if !current.is_object() {
+                        *current = Value::Object(Map::new());
+                    }
+                    current = current
+                        .as_object_mut()
+                        .expect("value was just made an object")
+                        .entry(key.clone())
+                        .or_insert(Value::Null);
+                    path.push(key);
+                }
+                Segment::Index(_) | Segment::Append => {
+                    let Some(items) = self.items(node) else {
+                        return Err(OverrideError::NotAList(path.join(".")));
+                    };
+                    nullable = self.nullable(items);
+                    node = self.resolve(items);
+                    if current.is_null() {
+                        *current = Value::Array(vec![]);
+                    }
+                    let Some(list) = current.as_array_mut() else {
+                        return Err(OverrideError::NotAList(path.join(".")));
+                    };
+                    let index = match segment {
+                        Segment::Append => {
+                            list.push(Value::Null);
+                            list.len() - 1
+                        }
+                        Segment::Index(index) => {
+                            let len = list.len();
+                            let resolved = if index < 0 { len as i64 + index } else { index };
+                            if resolved < 0 || resolved >= len as i64 {
+                                return Err(OverrideError::IndexOutOfRange { index, len });
+                            }
+                            resolved as usize
+                        }
+                        Segment::Key(_) => unreachable!(),
+                    };
+                    current = &mut list[index];
+                    path.push(index.to_string());
+                }
+            }
+        }
+
+        *current = match raw {
+            "null" if nullable => Value::Null,
+            raw => self.coerce(node, raw)?,
+        };
+        Ok(path.join("."))
+    }
+
+    fn resolve<'a>(&'a self, node: &'a Value) -> &'a Value {
+        if let Some(name) = node
+            .get("$ref")
+            .and_then(Value::as_str)
+            .and_then(|r| r.strip_prefix("#/$defs/"))
+        {
+            if let Some(def) = self.schema.get("$defs").and_then(|d| d.get(name)) {
+                return self.resolve(def);
+            }
+        }
+        if let Some(variants) = node.get("anyOf").and_then(Value::as_array) {
+            if let Some(variant) = variants
+                .iter()
+                .find(|v| v.get("type").and_then(Value::as_str) != Some("null"))
+            {
+                return self.resolve(variant);
+            }
+        }
+        node
+    }
+
+    // resolve() drops the null branch of an optional field, so whether null
+    // is allowed has to be checked on the unresolved node.
+    fn nullable(&self, node: &Value) -> bool {
+        let null_type = match node.get("type") {
+            Some(Value::String(t)) => t == "null",
+            Some(Value::Array(t)) => t.iter().any(|t| t == "null"),
+            _ => false,
+        };
+        null_type
+            || node
+                .get("anyOf")
+                .and_then(Value::as_array)
+                .is_some_and(|variants| variants.iter().any(|v| self.nullable(v)))
+    }
+
+    fn child<'a>(&'a self, node: &'a Value, key: &str) -> Option<&'a Value> {
+        if let Some(child) = node.get("properties").and_then(|p| p.get(key)) {
+            return Some(child);
+        }
+        match node.get("additionalProperties") {
+            Some(Value::Bool(false)) | None => None,
+            Some(child) => Some(child),
+        }
+    }
+
+    fn items<'a>(&'a self, node: &'a Value) -> Option<&'a Value> {
+        node.get("items")
+    }
+
+    fn keys(&self, node: &Value) -> Vec<String> {
+        node.get("properties")
+            .and_then(Value::as_object)
+            .map(|p| p.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn coerce(&self, node: &Value, raw: &str) -> Result<Value, OverrideError> {
+        let variants = enum_values(node);
+        if !variants.is_empty() {
+            if variants.iter().any(|v| v == raw) {
+                return Ok(Value::String(raw.to_string()));
+            }
+            return Err(OverrideError::InvalidEnumValue {
+                value: raw.to_string(),
+                expected: variants,
+            });
+        }
+
+        let types: Vec<&str> = match node.get("type") {
+            Some(Value::String(t)) => vec![t.as_str()],
+            Some(Value::Array(t)) => t.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        let wrong_type = |expected: &str| OverrideError::WrongType {
+            value: raw.to_string(),
+            expected: expected.to_string(),
+        };
+
+        if types.contains(&"string") {
+            Ok(Value::String(raw.to_string()))
+        } else if types.contains(&"boolean") {
+            raw.parse::<bool>()
+                .map(Value::Bool)
+                .map_err(|_| wrong_type("boolean"))
+        } else if types.contains(&"integer") {
+            raw.parse::<u64>()
+                .map(Number::from)
+                .or_else(|_| raw.parse::<i64>().map(Number::from))
+                .map(Value::Number)
+                .map_err(|_| wrong_type("integer"))
+        } else if types.contains(&"number") {
+            raw.parse::<f64>()
+                .ok()
+                .and_then(Number::from_f64)
+                .map(Value::Number)
+                .ok_or_else(|| wrong_type("number"))
+        } else {
+            let value: Value = serde_json::from_str(raw)
+                .map_err(|e| OverrideError::InvalidJson(raw.to_string(), e))?;
+            match (types.first(), &value) {
+                (Some(&"object"), Value::Object(_))
+                | (Some(&"array"), Value::Array(_))
+                | (None, _) => Ok(value),
+                (Some(expected), _) => Err(wrong_type(expected)),
+            }
+        }
+    }
+}
+
+fn enum_values(node: &Value) -> Vec<String> {
+    if let Some(values) = node.get("enum").and_then(Value::as_array) {
+        return values
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect();
+    }
+    node.get("oneOf")
+        .and_then(Value::as_array)
+        .map(|variants| variants.iter().flat_map(enum_values).collect())
+        .or_else(|| {
+            node.get("const")
+                .and_then(Value::as_str)
+                .map(|c| vec![c.to_string()])
+        })
+        .unwrap_or_default()
+}
+
+static KEY_SEGMENT_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^([a-zA-Z0-9_-]+)((?:\[(?:\+|-?[0-9]+)\])*)$").unwrap());
+
+static INDEX_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[(\+|-?[0-9]+)\]").unwrap());
+
+fn parse_key(key: &str) -> Result<Vec<Segment>, OverrideError> {
+    let mut segments = vec![];
+    for part in key.split('.') {
+        let captures = KEY_SEGMENT_REGEX
+            .captures(part)
+            .ok_or_else(|| OverrideError::InvalidKey(key.to_string()))?;
+        segments.push(Segment::Key(captures[1].to_string()));
+        for index in INDEX_REGEX.captures_iter(&captures[2]) {
+            segments.push(match &index[1] {
+                "+" => Segment::Append,
+                i => Segment::Index(
+                    i.parse()
+                        .map_err(|_| OverrideError::InvalidKey(key.to_string()))?,
+                ),
+            });
+        }
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn apply(value: &mut Value, key: &str, raw: &str) -> Result<String, OverrideError> {
+        Overrider::new().apply(value, key, raw)
+    }
+
+    #[test]
+    fn null_clears_optional_fields() {
+        let mut value = json!({ "name": "vm", "console": { "mode": "on" } });
+        apply(&mut value, "name", "null").unwrap();
+        apply(&mut value, "console.mode", "null").unwrap();
+        assert_eq!(value, json!({ "name": null, "console": { "mode": null } }));
+    }
+
+    #[test]
+    fn optional_fields_still_take_values() {
+        let mut value = json!({});
+        apply(&mut value, "name", "vm").unwrap();
+        apply(&mut value, "console.mode", "serial").unwrap();
+        assert_eq!(
+            value,
+            json!({ "name": "vm", "console": { "mode": "serial" } })
+        );
+        assert!(matches!(
+            apply(&mut value, "console.mode", "loud"),
+            Err(OverrideError::InvalidEnumValue { .. })
+        ));
+    }
+
+    #[test]
+    fn null_is_a_string_where_null_is_not_allowed() {
+        let mut value = json!({});
+        apply(&mut value, "kernel_path", "null").unwrap();
+        assert_eq!(value, json!({ "kernel_path": "null" }));
+        assert!(apply(&mut value, "cpu.cores", "null").is_err());
+    }
+
+    #[test]
+    fn extends_cannot_be_overridden() {
+        let mut value = json!({});
+        assert!(matches!(
+            apply(&mut value, "extends", "base.json"),
+            Err(OverrideError::Extends)
+        ));
+        assert!(matches!(
+            apply(&mut value, "extends[+]", "base.json"),
+            Err(OverrideError::Extends)
+        ));
+        assert_eq!(value, json!({}));
+    }
+}
@@ -0,0 +1,15 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
+#[serde(default)]
+#[schemars(deny_unknown_fields)]
+pub struct Shutdown {
+    pub grace_period: u64,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self { grace_period: 30 }
+    }
+}
@@ -0,0 +1,52 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
+#[serde(default)]
+#[schemars(deny_unknown_fields)]
+pub struct Support {
+    pub socket_timeout: u64,
+    pub policy: Policy,
+    pub policies: BTreeMap<String, Policy>,
+    pub max_restarts: u64,
+}
+
+impl Default for Support {
+    fn default() -> Self {
+        Self {
+            socket_timeout: 30,
+            policy: Policy::default(),
+            policies: BTreeMap::new(),
+            max_restarts: 3,
+        }
+    }
+}
+
+impl Support {
+    pub fn policy_for(&self, name: &str) -> Policy {
+        self.policies.get(name).cloned().unwrap_or(self.policy.clone())
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone, Debug, Eq, PartialEq)]
+pub enum Policy {
+    #[default]
+    #[serde(rename = "shutdown")]
+    Shutdown,
+    #[serde(rename = "restart")]
+    Restart,
+    #[serde(rename = "ignore")]
+    Ignore,
+}
+
+impl Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Policy::Shutdown => write!(f, "shutdown"),
+            Policy::Restart => write!(f, "restart"),
+            Policy::Ignore => write!(f, "ignore"),
+        }
+    }
+}
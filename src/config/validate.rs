@@ -0,0 +1,395 @@
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use thiserror::Error;
+
+use crate::config::{console, filesystem, Config};
+
+impl Config {
+    pub fn validate(&self) -> Report {
+        let mut report = Report::default();
+
+        if let Some(name) = self.name.clone() {
+            report.check_identifier("name", name);
+        }
+
+        report.check_exists("kernel_path", &self.kernel_path);
+        report.check_exists("initrd_path", &self.initrd_path);
+
+        for (key, binary) in [
+            ("binaries.cloud_hypervisor", &self.binaries.cloud_hypervisor),
+            ("binaries.virtiofsd", &self.binaries.virtiofsd),
+            ("binaries.crosvm", &self.binaries.crosvm),
+        ] {
+            if let Some(binary) = binary.as_ref().filter(|b| b.components().count() > 1) {
+                report.check_exists(key, binary);
+            }
+        }
+
+        let mut share_tags = HashMap::new();
+        for (i, share) in self.filesystem.shares.iter().enumerate() {
+            let path = format!("filesystem.shares[{}]", i);
+            report.check_identifier(format!("{}.tag", path), share.tag.clone());
+            report.check_duplicate(&mut share_tags, format!("{}.tag", path), &share.tag);
+            report.check_exists(format!("{}.source", path), &share.source);
+        }
+
+        for helper in self.support.policies.keys() {
+            let known = helper == "gpu"
+                || helper
+                    .strip_prefix("virtiofs-")
+                    .is_some_and(|tag| self.filesystem.shares.iter().any(|s| s.tag == tag));
+            if !known {
+                report.push(
+                    format!("support.policies.{}", helper),
+                    ProblemKind::UnknownHelper(helper.clone()),
+                );
+            }
+        }
+
+        let (console, serial) = self.console.devices();
+        if self.console.mode.is_some()
+            && (self.console.console != console::Device::default()
+                || self.console.serial != console::Device::default())
+        {
+            report.push("console.mode", ProblemKind::ConsoleModeCombined);
+        }
+        for (key, device) in [("console.console", &console), ("console.serial", &serial)] {
+            if device.sink == console::Sink::File && device.file.is_none() {
+                report.push(format!("{}.file", key), ProblemKind::ConsoleFileMissing);
+            }
+        }
+        if console.sink == console::Sink::Tty && serial.sink == console::Sink::Tty {
+            report.push("console.serial.sink", ProblemKind::ConsoleTtyTaken);
+        }
+
+        let data_dir = dirs::data_dir().map(|p| p.join("contain"));
+        let mut disk_tags = HashMap::new();
+        for (i, disk) in self.filesystem.disks.iter().enumerate() {
+            let path = format!("filesystem.disks[{}]", i);
+            report.check_identifier(format!("{}.tag", path), disk.tag.clone());
+            report.check_duplicate(&mut disk_tags, format!("{}.tag", path), &disk.tag);
+
+            let location = match data_dir.as_deref() {
+                Some(data_dir) => disk.resolve_path(&self.name, data_dir),
+                None => disk.source.clone(),
+            };
+            let Some(location) = location else {
+                report.push(
+                    path,
+                    match self.name {
+                        Some(_) => ProblemKind::DataDirUnavailable,
+                        None => ProblemKind::UnresolvableDiskLocation,
+                    },
+                );
+                continue;
+            };
+
+            let exists = match location.try_exists() {
+                Ok(b) => b,
+                Err(e) => {
+                    report.push(path, ProblemKind::PathInaccessible(location, e));
+                    continue;
+                }
+            };
+            if exists {
+                continue;
+            }
+            if !disk.create {
+                report.push(format!("{}.source", path), ProblemKind::PathMissing(location));
+                continue;
+            }
+            if disk.size == 0 {
+                report.push(format!("{}.size", path), ProblemKind::ZeroSizedDisk);
+            }
+            if disk.format != filesystem::Format::Qcow2 {
+                report.push(
+                    format!("{}.format", path),
+                    ProblemKind::UnsupportedDiskCreation(disk.format.clone()),
+                );
+            }
+        }
+
+        report
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    fn push(&mut self, path: impl Into<String>, kind: ProblemKind) {
+        self.problems.push(Problem {
+            path: path.into(),
+            kind,
+        });
+    }
+
+    fn check_identifier(&mut self, path: impl Into<String>, value: String) {
+        if let Err(e) = value.check_is_valid_identifier() {
+            self.push(path, ProblemKind::InvalidIdentifier(e));
+        }
+    }
+
+    fn check_exists(&mut self, path: impl Into<String>, location: &Path) {
+        match location.try_exists() {
+            Ok(true) => (),
+            Ok(false) => self.push(path, ProblemKind::PathMissing(location.to_path_buf())),
+            Err(e) => self.push(path, ProblemKind::PathInaccessible(location.to_path_buf(), e)),
+        }
+    }
+
+    fn check_duplicate(&mut self, seen: &mut HashMap<String, String>, path: String, tag: &str) {
+        match seen.get(tag) {
+            Some(first) => self.push(
+                path,
+                ProblemKind::DuplicateTag(tag.to_string(), first.clone()),
+            ),
+            None => {
+                seen.insert(tag.to_string(), path);
+            }
+        }
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for problem in self.problems.iter() {
+            writeln!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Report {}
+
+#[derive(Error, Debug)]
+#[error("{path}: {kind}")]
+pub struct Problem {
+    pub path: String,
+    pub kind: ProblemKind,
+}
+
+#[derive(Error, Debug)]
+pub enum ProblemKind {
+    #[error("{0}")]
+    InvalidIdentifier(IdentifierValidationError),
+    #[error("{0:?} does not exist")]
+    PathMissing(PathBuf),
+    #[error("unable to access {0:?}")]
+    PathInaccessible(PathBuf, #[source] io::Error),
+    #[error("disk location cannot be resolved, either set a source or a vm name")]
+    UnresolvableDiskLocation,
+    #[error("data dir unavailable")]
+    DataDirUnavailable,
+    #[error("tag \"{0}\" is already used by {1}")]
+    DuplicateTag(String, String),
+    #[error("disk would be created with a size of zero")]
+    ZeroSizedDisk,
+    #[error("creating {0} disks is not supported")]
+    UnsupportedDiskCreation(filesystem::Format),
+    #[error("there is no support process \"{0}\", expected gpu or virtiofs-<share tag>")]
+    UnknownHelper(String),
+    #[error("mode cannot be combined with separate console and serial sections")]
+    ConsoleModeCombined,
+    #[error("a file is required for the file sink")]
+    ConsoleFileMissing,
+    #[error("the tty is already used by console.console, only one device can use it")]
+    ConsoleTtyTaken,
+}
+
+pub(crate) trait CheckIsValidIdentifier {
+    fn check_is_valid_identifier(self) -> Result<String, IdentifierValidationError>;
+}
+
+impl CheckIsValidIdentifier for String {
+    fn check_is_valid_identifier(self) -> Result<String, IdentifierValidationError> {
+        let regex = &*IDENTIFIER_REGEX;
+        if regex.is_match(self.as_str()) {
+            Ok(self)
+        } else {
+            Err(IdentifierValidationError(self))
+        }
+    }
+}
+
+static IDENTIFIER_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-zA-Z0-9_-][a-zA-Z0-9\._-]*$").unwrap());
+
+#[derive(Error, Debug)]
+pub struct IdentifierValidationError(String);
+
+impl Display for IdentifierValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            format!(
+                "\"{}\" is not a valid identifier, should match {}",
+                self.0, &*IDENTIFIER_REGEX,
+            )
+            .as_str(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::env;
+
+    fn validate(mut value: Value) -> Vec<Problem> {
+        value["kernel_path"] = json!("/");
+        value["initrd_path"] = json!("/");
+        Config::from_deserializer(value, true)
+            .unwrap()
+            .validate()
+            .problems
+    }
+
+    fn paths(problems: &[Problem]) -> Vec<&str> {
+        problems.iter().map(|p| p.path.as_str()).collect()
+    }
+
+    #[test]
+    fn valid_config_has_no_problems() {
+        let problems = validate(json!({
+            "name": "vm",
+            "filesystem": { "shares": [{ "source": "/", "tag": "root" }] },
+            "support": { "policies": { "gpu": "restart", "virtiofs-root": "ignore" } },
+        }));
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn identifiers_are_checked() {
+        let problems = validate(json!({
+            "name": ".vm",
+            "filesystem": {
+                "shares": [{ "source": "/", "tag": "a/b" }],
+                "disks": [{ "source": "/", "tag": "" }],
+            },
+        }));
+        assert_eq!(
+            paths(&problems),
+            [
+                "name",
+                "filesystem.shares[0].tag",
+                "filesystem.disks[0].tag"
+            ]
+        );
+        assert!(problems
+            .iter()
+            .all(|p| matches!(p.kind, ProblemKind::InvalidIdentifier(_))));
+    }
+
+    #[test]
+    fn duplicate_tags_point_at_the_first_use() {
+        let problems = validate(json!({
+            "filesystem": {
+                "shares": [
+                    { "source": "/", "tag": "root" },
+                    { "source": "/", "tag": "root" },
+                ],
+                "disks": [
+                    { "source": "/", "tag": "root" },
+                    { "source": "/", "tag": "data" },
+                    { "source": "/", "tag": "data" },
+                ],
+            },
+        }));
+        assert_eq!(
+            paths(&problems),
+            ["filesystem.shares[1].tag", "filesystem.disks[2].tag"]
+        );
+        assert!(matches!(
+            &problems[0].kind,
+            ProblemKind::DuplicateTag(tag, first) if tag == "root" && first == "filesystem.shares[0].tag"
+        ));
+        assert!(matches!(
+            &problems[1].kind,
+            ProblemKind::DuplicateTag(tag, first) if tag == "data" && first == "filesystem.disks[1].tag"
+        ));
+    }
+
+    #[test]
+    fn unknown_helper_policies_are_reported() {
+        let problems = validate(json!({
+            "filesystem": { "shares": [{ "source": "/", "tag": "root" }] },
+            "support": { "policies": { "virtiofs-home": "restart", "gpu2": "ignore" } },
+        }));
+        assert_eq!(
+            paths(&problems),
+            ["support.policies.gpu2", "support.policies.virtiofs-home"]
+        );
+        assert!(matches!(
+            &problems[1].kind,
+            ProblemKind::UnknownHelper(helper) if helper == "virtiofs-home"
+        ));
+    }
+
+    #[test]
+    fn console_sink_conflicts_are_reported() {
+        let problems = validate(json!({
+            "console": { "mode": "on", "serial": { "sink": "log" } },
+        }));
+        assert_eq!(paths(&problems), ["console.mode"]);
+        assert!(matches!(problems[0].kind, ProblemKind::ConsoleModeCombined));
+
+        let problems = validate(json!({
+            "console": { "console": { "sink": "file" }, "serial": { "sink": "file" } },
+        }));
+        assert_eq!(
+            paths(&problems),
+            ["console.console.file", "console.serial.file"]
+        );
+        assert!(problems
+            .iter()
+            .all(|p| matches!(p.kind, ProblemKind::ConsoleFileMissing)));
+
+        let problems = validate(json!({
+            "console": { "console": { "sink": "tty" }, "serial": { "sink": "tty" } },
+        }));
+        assert_eq!(paths(&problems), ["console.serial.sink"]);
+        assert!(matches!(problems[0].kind, ProblemKind::ConsoleTtyTaken));
+    }
+
+    #[test]
+    fn disk_creation_is_checked() {
+        let missing = env::temp_dir().join(format!("contain-validate-{}.img", std::process::id()));
+        let problems = validate(json!({
+            "filesystem": { "disks": [
+                { "source": missing, "tag": "missing", "create": false },
+                { "source": missing, "tag": "raw", "create": true, "size": 0, "format": "raw" },
+                { "tag": "unnamed", "create": true, "size": 1 },
+            ] },
+        }));
+        assert_eq!(
+            paths(&problems),
+            [
+                "filesystem.disks[0].source",
+                "filesystem.disks[1].size",
+                "filesystem.disks[1].format",
+                "filesystem.disks[2]",
+            ]
+        );
+        assert!(matches!(&problems[0].kind, ProblemKind::PathMissing(path) if *path == missing));
+        assert!(matches!(problems[1].kind, ProblemKind::ZeroSizedDisk));
+        assert!(matches!(
+            problems[2].kind,
+            ProblemKind::UnsupportedDiskCreation(filesystem::Format::Raw)
+        ));
+        assert!(matches!(
+            problems[3].kind,
+            ProblemKind::UnresolvableDiskLocation
+        ));
+    }
+}
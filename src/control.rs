@@ -0,0 +1,84 @@
+use std::fmt::Display;
+use thiserror::Error;
+
+use crate::client::RequestError;
+use crate::hypervisor::{ApiClient, VmState, API_SOCKET};
+use crate::instance::{self, Instance, InstanceError};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Pause,
+    Resume,
+    Reboot,
+    PowerOff,
+}
+
+impl Action {
+    fn allowed_in(&self, state: VmState) -> bool {
+        match self {
+            Action::Resume => state == VmState::Paused,
+            Action::Pause | Action::Reboot | Action::PowerOff => state == VmState::Running,
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Pause => write!(f, "pause"),
+            Action::Resume => write!(f, "resume"),
+            Action::Reboot => write!(f, "reboot"),
+            Action::PowerOff => write!(f, "power off"),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ControlError {
+    #[error("unable to find vm")]
+    Instance(#[from] InstanceError),
+    #[error("unable to talk to the hypervisor of vm {id}")]
+    Request {
+        id: String,
+        #[source]
+        source: RequestError,
+    },
+    #[error("cannot {action} vm {id} because it is {state}")]
+    InvalidState {
+        id: String,
+        action: Action,
+        state: VmState,
+    },
+}
+
+pub fn api_client(instance: &Instance) -> ApiClient {
+    ApiClient::new(instance.dir.join(API_SOCKET))
+}
+
+pub async fn control(vm: &str, action: Action) -> Result<Instance, ControlError> {
+    let instance = instance::find(vm)?;
+    let api = api_client(&instance);
+    let request_error = |source| ControlError::Request {
+        id: instance.info.id.clone(),
+        source,
+    };
+
+    let state = api.info().await.map_err(request_error)?.state;
+    if !action.allowed_in(state) {
+        return Err(ControlError::InvalidState {
+            id: instance.info.id.clone(),
+            action,
+            state,
+        });
+    }
+
+    match action {
+        Action::Pause => api.pause().await,
+        Action::Resume => api.resume().await,
+        Action::Reboot => api.reboot().await,
+        Action::PowerOff => api.power_button().await,
+    }
+    .map_err(request_error)?;
+
+    Ok(instance)
+}
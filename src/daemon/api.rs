@@ -1,32 +1,62 @@
+use axum::extract::{ConnectInfo, State};
 use axum::response::IntoResponse;
 use axum::{http::StatusCode, routing::post, Json, Router};
 use rand::{distr::Alphanumeric, Rng};
 use regex::Regex;
 use std::process::Command;
+use std::sync::Arc;
 
+use crate::daemon::owners::{Owners, Peer};
 use crate::daemon::requests::*;
 use crate::daemon::MANAGED_RESOURCES_PREFIX;
 
-pub(crate) fn root() -> Router {
-    Router::new().nest("/api", api())
+pub(crate) fn root(owners: Arc<Owners>) -> Router {
+    Router::new().nest("/api", api()).with_state(owners)
 }
 
-fn api() -> Router {
+fn api() -> Router<Arc<Owners>> {
     Router::new().nest("/net", net())
 }
 
-fn net() -> Router {
+fn net() -> Router<Arc<Owners>> {
     Router::new().route("/tap", post(tap_create).delete(tap_delete))
 }
 
-async fn tap_create(Json(req): Json<NetTapCreateRequest>) -> impl IntoResponse {
-    let id: String = rand::rng()
-        .sample_iter(&Alphanumeric)
-        .take(7)
-        .map(char::from)
-        .collect();
-    let name = format!("{}{}", MANAGED_RESOURCES_PREFIX, id);
+async fn tap_create(
+    State(owners): State<Arc<Owners>>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    Json(req): Json<NetTapCreateRequest>,
+) -> impl IntoResponse {
+    let Some(uid) = peer.uid else {
+        return StatusCode::FORBIDDEN.into_response();
+    };
     let regex = Regex::new(r"^[a-zA-Z0-9\._-]+$").unwrap();
+    let name = match req.name {
+        Some(name) => {
+            if !name.starts_with(MANAGED_RESOURCES_PREFIX) {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+            if !regex.is_match(name.as_str()) {
+                return StatusCode::BAD_REQUEST.into_response();
+            }
+            // Only names this daemon handed out to the same user can be reused.
+            if owners.owner(&name) != Some(uid) {
+                return StatusCode::FORBIDDEN.into_response();
+            }
+            name
+        }
+        None => loop {
+            let id: String = rand::rng()
+                .sample_iter(&Alphanumeric)
+                .take(7)
+                .map(char::from)
+                .collect();
+            let name = format!("{}{}", MANAGED_RESOURCES_PREFIX, id);
+            if owners.owner(&name).is_none() {
+                break name;
+            }
+        },
+    };
     let user = req.user;
 
     if !regex.is_match(user.as_str()) {
@@ -70,15 +100,30 @@ async fn tap_create(Json(req): Json<NetTapCreateRequest>) -> impl IntoResponse {
             return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
     }
+    if owners.record(&name, uid).is_err() {
+        _ = Command::new("ip")
+            .args(["link", "delete", name.as_str()])
+            .output();
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
 
     (StatusCode::CREATED, Json(NetTapCreateResponse { name })).into_response()
 }
 
-async fn tap_delete(Json(req): Json<NetTapDeleteRequest>) -> impl IntoResponse {
+async fn tap_delete(
+    State(owners): State<Arc<Owners>>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    Json(req): Json<NetTapDeleteRequest>,
+) -> impl IntoResponse {
     let name = req.name;
     if !name.starts_with(MANAGED_RESOURCES_PREFIX) {
         return StatusCode::FORBIDDEN;
     }
+    match peer.uid {
+        Some(0) => (),
+        Some(uid) if owners.owner(&name) == Some(uid) => (),
+        _ => return StatusCode::FORBIDDEN,
+    }
 
     match Command::new("ip")
         .args(["link", "delete", name.as_str()])
@@ -93,6 +138,9 @@ async fn tap_delete(Json(req): Json<NetTapDeleteRequest>) -> impl IntoResponse {
             return StatusCode::INTERNAL_SERVER_ERROR;
         }
     }
+    if !req.keep_owner && owners.forget(&name).is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
 
     StatusCode::ACCEPTED
 }
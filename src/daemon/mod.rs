@@ -1,9 +1,11 @@
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
 use std::{error::Error, path::Path};
 use tokio::net::UnixListener;
 
 pub mod api;
+pub mod owners;
 pub mod requests;
 
 pub static MANAGED_RESOURCES_PREFIX: &str = "contain-";
@@ -12,10 +14,15 @@ pub static DEFAULT_SOCKET_PATH: &str = "/run/contain.sock";
 pub async fn serve_api_on_unix_socket() -> Result<(), Box<dyn Error + Send + Sync>> {
     let socket_path = DEFAULT_SOCKET_PATH.to_string();
     let socket = create_socket(socket_path.clone())?;
+    let owners = Arc::new(owners::Owners::load(Path::new(owners::DEFAULT_OWNERS_PATH))?);
 
     println!("Serving api at {}.", socket_path);
 
-    axum::serve(socket, api::root()).await?;
+    axum::serve(
+        socket,
+        api::root(owners).into_make_service_with_connect_info::<owners::Peer>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -0,0 +1,115 @@
+use axum::extract::connect_info::Connected;
+use axum::serve::IncomingStream;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::{fs, io};
+use tokio::net::UnixListener;
+
+pub static DEFAULT_OWNERS_PATH: &str = "/var/lib/contain/taps.json";
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Peer {
+    pub(crate) uid: Option<u32>,
+}
+
+impl Connected<IncomingStream<'_, UnixListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, UnixListener>) -> Self {
+        Self {
+            uid: stream.io().peer_cred().ok().map(|cred| cred.uid()),
+        }
+    }
+}
+
+// A name is forgotten when its tap is deleted, unless the client asks to keep
+// it because a snapshot refers to the tap. The snapshot can then be restored
+// later with the same name by the same user, even across daemon restarts.
+pub(crate) struct Owners {
+    path: PathBuf,
+    owners: Mutex<BTreeMap<String, u32>>,
+}
+
+impl Owners {
+    pub(crate) fn load(path: &Path) -> io::Result<Self> {
+        let owners = match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            owners: Mutex::new(owners),
+        })
+    }
+
+    pub(crate) fn owner(&self, name: &str) -> Option<u32> {
+        self.owners.lock().unwrap().get(name).copied()
+    }
+
+    pub(crate) fn record(&self, name: &str, uid: u32) -> io::Result<()> {
+        let mut owners = self.owners.lock().unwrap();
+        if owners.get(name) == Some(&uid) {
+            return Ok(());
+        }
+        owners.insert(name.to_string(), uid);
+        self.save(&owners)
+    }
+
+    pub(crate) fn forget(&self, name: &str) -> io::Result<()> {
+        let mut owners = self.owners.lock().unwrap();
+        if owners.remove(name).is_none() {
+            return Ok(());
+        }
+        self.save(&owners)
+    }
+
+    fn save(&self, owners: &BTreeMap<String, u32>) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = self.path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_string_pretty(owners)?)?;
+        fs::rename(temp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_owners_survive_a_reload() {
+        let dir = std::env::temp_dir().join(format!("contain-owners-{}", std::process::id()));
+        let path = dir.join("taps.json");
+        _ = fs::remove_dir_all(&dir);
+
+        let owners = Owners::load(&path).unwrap();
+        assert_eq!(owners.owner("contain-abc"), None);
+        owners.record("contain-abc", 1000).unwrap();
+        owners.record("contain-def", 1001).unwrap();
+
+        let owners = Owners::load(&path).unwrap();
+        assert_eq!(owners.owner("contain-abc"), Some(1000));
+        assert_eq!(owners.owner("contain-def"), Some(1001));
+        assert_eq!(owners.owner("contain-ghi"), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn forgotten_owners_stay_forgotten() {
+        let dir = std::env::temp_dir().join(format!("contain-forget-{}", std::process::id()));
+        let path = dir.join("taps.json");
+        _ = fs::remove_dir_all(&dir);
+
+        let owners = Owners::load(&path).unwrap();
+        owners.record("contain-abc", 1000).unwrap();
+        owners.record("contain-def", 1001).unwrap();
+        owners.forget("contain-abc").unwrap();
+        owners.forget("contain-ghi").unwrap();
+
+        let owners = Owners::load(&path).unwrap();
+        assert_eq!(owners.owner("contain-abc"), None);
+        assert_eq!(owners.owner("contain-def"), Some(1001));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,7 +2,9 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NetTapCreateRequest {
-    pub user: String
+    pub user: String,
+    #[serde(default)]
+    pub name: Option<String>
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -12,5 +14,7 @@ pub struct NetTapCreateResponse {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NetTapDeleteRequest {
-    pub name: String
+    pub name: String,
+    #[serde(default)]
+    pub keep_owner: bool
 }
@@ -0,0 +1,383 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::{env, io};
+use thiserror::Error;
+
+use crate::client::{
+    delete_tap_device, request_tap_device, start_share_helper, stop_helper, RequestError,
+};
+use crate::config::filesystem::{Disk, Format, Share};
+use crate::config::validate::{CheckIsValidIdentifier, IdentifierValidationError};
+use crate::control::api_client;
+use crate::hypervisor::config::{DiskConfig, FsConfig, NetConfig};
+use crate::instance::{self, Instance, InstanceError};
+use crate::run::api::RUNNER_SOCKET;
+use crate::run::plan::{share_helper, share_socket, DiskCreation};
+use crate::run::{create_disk, VmError};
+
+pub static HOTPLUG_FILE: &str = "hotplug.json";
+pub static HOTPLUG_LOCK_FILE: &str = "hotplug.lock";
+
+#[derive(Error, Debug)]
+pub enum HotplugError {
+    #[error("unable to find vm")]
+    Instance(#[from] InstanceError),
+    #[error("unable to talk to the hypervisor of vm {id}")]
+    Request {
+        id: String,
+        #[source]
+        source: RequestError,
+    },
+    #[error("error while taking to daemon")]
+    DaemonRequest(#[source] RequestError),
+    #[error("cannot read environment variable USER to determine current user")]
+    UserEnvUnavailable(env::VarError),
+    #[error("current dir unavailable")]
+    CurrentDirUnavailable(io::Error),
+    #[error("data dir unavailable")]
+    DataDirUnavailable,
+    #[error("invalid tag")]
+    InvalidTag(IdentifierValidationError),
+    #[error("vm {0} has no name, pass the source of the disk")]
+    Unnamed(String),
+    #[error("device {0} is already attached")]
+    AlreadyAttached(String),
+    #[error("invalid disk source {0:?}")]
+    InvalidDiskSource(PathBuf, #[source] io::Error),
+    #[error("disk would be created with a size of zero")]
+    ZeroSizedDisk,
+    #[error("creating {0} disks is not supported")]
+    UnsupportedDiskCreation(Format),
+    #[error("failed to create disk")]
+    FailedToCreateDisk(#[source] Box<VmError>),
+    #[error("invalid share source {0:?}")]
+    InvalidShareSource(PathBuf, #[source] io::Error),
+    #[error("unable to talk to the runner of vm {id}")]
+    Runner {
+        id: String,
+        #[source]
+        source: RequestError,
+    },
+    #[error("unable to access hotplug record {0:?}")]
+    Io(PathBuf, #[source] io::Error),
+    #[error("invalid hotplug record {0:?}")]
+    Invalid(PathBuf, #[source] serde_json::Error),
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq)]
+pub struct Hotplugged {
+    pub devices: Vec<HotpluggedDevice>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct HotpluggedDevice {
+    pub id: String,
+    #[serde(flatten)]
+    pub kind: DeviceKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(tag = "kind")]
+pub enum DeviceKind {
+    #[serde(rename = "disk")]
+    Disk { path: PathBuf },
+    #[serde(rename = "share")]
+    Share { tag: String, pid: u32 },
+    #[serde(rename = "net")]
+    Net { tap_device: String },
+}
+
+pub struct HotplugLock {
+    _file: File,
+}
+
+impl HotplugLock {
+    pub fn acquire(dir: &Path) -> Result<Self, HotplugError> {
+        let path = dir.join(HOTPLUG_LOCK_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| HotplugError::Io(path.clone(), e))?;
+        instance::lock(&file).map_err(|e| HotplugError::Io(path, e))?;
+        Ok(Self { _file: file })
+    }
+}
+
+impl Hotplugged {
+    pub fn read(dir: &Path) -> Result<Self, HotplugError> {
+        let path = dir.join(HOTPLUG_FILE);
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(HotplugError::Io(path, e)),
+        };
+        serde_json::from_str(&json).map_err(|e| HotplugError::Invalid(path, e))
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<(), HotplugError> {
+        let path = dir.join(HOTPLUG_FILE);
+        let json = serde_json::to_string_pretty(self).expect("hotplug record is valid json");
+        fs::write(&path, json).map_err(|e| HotplugError::Io(path, e))
+    }
+
+    fn check_free(&self, id: &str) -> Result<(), HotplugError> {
+        if self.devices.iter().any(|d| d.id == id) {
+            return Err(HotplugError::AlreadyAttached(id.to_string()));
+        }
+        Ok(())
+    }
+}
+
+pub async fn attach_disk(
+    vm: &str,
+    disk: &Disk,
+    readonly: bool,
+) -> Result<HotpluggedDevice, HotplugError> {
+    let instance = instance::find(vm)?;
+    let tag = disk
+        .tag
+        .clone()
+        .check_is_valid_identifier()
+        .map_err(HotplugError::InvalidTag)?;
+    let id = format!("disk-{}", tag);
+    let _lock = HotplugLock::acquire(&instance.dir)?;
+    let mut hotplugged = Hotplugged::read(&instance.dir)?;
+    hotplugged.check_free(&id)?;
+
+    let data_dir = dirs::data_dir()
+        .map(|p| p.join("contain"))
+        .ok_or(HotplugError::DataDirUnavailable)?;
+    let path = disk
+        .resolve_path(&instance.info.name, &data_dir)
+        .ok_or_else(|| HotplugError::Unnamed(instance.info.id.clone()))?;
+    let path = env::current_dir()
+        .map_err(HotplugError::CurrentDirUnavailable)?
+        .join(path);
+
+    let exists = path
+        .try_exists()
+        .map_err(|e| HotplugError::InvalidDiskSource(path.clone(), e))?;
+    let created = !exists && disk.create;
+    if created {
+        if disk.size == 0 {
+            return Err(HotplugError::ZeroSizedDisk);
+        }
+        if disk.format != Format::Qcow2 {
+            return Err(HotplugError::UnsupportedDiskCreation(disk.format.clone()));
+        }
+        let creation = DiskCreation {
+            size: disk.size,
+            format: disk.format.clone(),
+        };
+        create_disk(&path, &creation).map_err(|e| HotplugError::FailedToCreateDisk(Box::new(e)))?;
+    }
+
+    let added = api_client(&instance)
+        .add_disk(&DiskConfig {
+            path: path.clone(),
+            readonly: readonly || !disk.write,
+            serial: tag,
+            id: Some(id.clone()),
+        })
+        .await;
+    if let Err(source) = added {
+        if created {
+            let _ = fs::remove_file(&path);
+        }
+        return Err(request_error(&instance, source));
+    }
+
+    let device = HotpluggedDevice {
+        id,
+        kind: DeviceKind::Disk { path },
+    };
+    hotplugged.devices.push(device.clone());
+    hotplugged.write(&instance.dir)?;
+    Ok(device)
+}
+
+pub async fn attach_share(vm: &str, share: &Share) -> Result<HotpluggedDevice, HotplugError> {
+    let instance = instance::find(vm)?;
+    let tag = share
+        .tag
+        .clone()
+        .check_is_valid_identifier()
+        .map_err(HotplugError::InvalidTag)?;
+    let id = format!("fs-{}", tag);
+    let _lock = HotplugLock::acquire(&instance.dir)?;
+    let mut hotplugged = Hotplugged::read(&instance.dir)?;
+    hotplugged.check_free(&id)?;
+
+    let source = fs::canonicalize(&share.source)
+        .map_err(|e| HotplugError::InvalidShareSource(share.source.clone(), e))?;
+    let runner = instance.dir.join(RUNNER_SOCKET);
+    let helper = start_share_helper(
+        &runner,
+        Share {
+            source,
+            tag: tag.clone(),
+            ..share.clone()
+        },
+    )
+    .await
+    .map_err(|source| runner_error(&instance, source))?;
+    let socket = share_socket(&tag);
+
+    let added = api_client(&instance)
+        .add_fs(&FsConfig {
+            tag: tag.clone(),
+            socket: instance.dir.join(&socket),
+            num_queues: 1,
+            queue_size: 1024,
+            id: Some(id.clone()),
+        })
+        .await;
+    if let Err(source) = added {
+        _ = stop_helper(&runner, &helper.name).await;
+        return Err(request_error(&instance, source));
+    }
+
+    let device = HotpluggedDevice {
+        id,
+        kind: DeviceKind::Share {
+            tag,
+            pid: helper.pid,
+        },
+    };
+    hotplugged.devices.push(device.clone());
+    hotplugged.write(&instance.dir)?;
+    Ok(device)
+}
+
+pub async fn attach_net(vm: &str) -> Result<HotpluggedDevice, HotplugError> {
+    let instance = instance::find(vm)?;
+    let _lock = HotplugLock::acquire(&instance.dir)?;
+    let mut hotplugged = Hotplugged::read(&instance.dir)?;
+    let user = env::var("USER").map_err(HotplugError::UserEnvUnavailable)?;
+
+    let tap_device = request_tap_device(user, None)
+        .await
+        .map_err(HotplugError::DaemonRequest)?;
+    let id = format!("net-{}", tap_device);
+
+    let added = api_client(&instance)
+        .add_net(&NetConfig {
+            tap: tap_device.clone(),
+            num_queues: instance.info.net_queues,
+            id: Some(id.clone()),
+        })
+        .await;
+    if let Err(source) = added {
+        _ = delete_tap_device(tap_device, false).await;
+        return Err(request_error(&instance, source));
+    }
+
+    let device = HotpluggedDevice {
+        id,
+        kind: DeviceKind::Net { tap_device },
+    };
+    hotplugged.devices.push(device.clone());
+    hotplugged.write(&instance.dir)?;
+    Ok(device)
+}
+
+pub async fn detach(vm: &str, id: &str) -> Result<(), HotplugError> {
+    let instance = instance::find(vm)?;
+    let _lock = HotplugLock::acquire(&instance.dir)?;
+    let mut hotplugged = Hotplugged::read(&instance.dir)?;
+
+    api_client(&instance)
+        .remove_device(id)
+        .await
+        .map_err(|source| request_error(&instance, source))?;
+
+    let Some(i) = hotplugged.devices.iter().position(|d| d.id == id) else {
+        return Ok(());
+    };
+    let device = hotplugged.devices.remove(i);
+    hotplugged.write(&instance.dir)?;
+    if let DeviceKind::Share { tag, .. } = &device.kind {
+        stop_helper(&instance.dir.join(RUNNER_SOCKET), &share_helper(tag))
+            .await
+            .map_err(|source| runner_error(&instance, source))?;
+    }
+    release(&instance.dir, &device).await
+}
+
+pub async fn release_all(dir: &Path) -> Result<(), HotplugError> {
+    let _lock = HotplugLock::acquire(dir)?;
+    let hotplugged = Hotplugged::read(dir)?;
+    for device in hotplugged.devices.iter() {
+        if let Err(e) = release(dir, device).await {
+            eprintln!("failed to release hotplugged device {}: {}", device.id, e);
+        }
+    }
+    Ok(())
+}
+
+async fn release(dir: &Path, device: &HotpluggedDevice) -> Result<(), HotplugError> {
+    match &device.kind {
+        DeviceKind::Disk { .. } => Ok(()),
+        DeviceKind::Share { tag, .. } => {
+            _ = fs::remove_file(dir.join(share_socket(tag)));
+            Ok(())
+        }
+        DeviceKind::Net { tap_device } => delete_tap_device(tap_device.clone(), false)
+            .await
+            .map_err(HotplugError::DaemonRequest),
+    }
+}
+
+fn runner_error(instance: &Instance, source: RequestError) -> HotplugError {
+    HotplugError::Runner {
+        id: instance.info.id.clone(),
+        source,
+    }
+}
+
+fn request_error(instance: &Instance, source: RequestError) -> HotplugError {
+    HotplugError::Request {
+        id: instance.info.id.clone(),
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn concurrent_updates_keep_every_device() {
+        let dir = env::temp_dir().join(format!("contain-hotplug-lock-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let dir = dir.clone();
+                thread::spawn(move || {
+                    let _lock = HotplugLock::acquire(&dir).unwrap();
+                    let mut hotplugged = Hotplugged::read(&dir).unwrap();
+                    thread::sleep(std::time::Duration::from_millis(10));
+                    hotplugged.devices.push(HotpluggedDevice {
+                        id: format!("disk-{}", i),
+                        kind: DeviceKind::Disk {
+                            path: PathBuf::from("disk.img"),
+                        },
+                    });
+                    hotplugged.write(&dir).unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(Hotplugged::read(&dir).unwrap().devices.len(), 8);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct VmConfig {
+    pub payload: PayloadConfig,
+    pub cpus: CpusConfig,
+    pub memory: MemoryConfig,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub disks: Vec<DiskConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fs: Vec<FsConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub net: Vec<NetConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub gpu: Vec<GpuConfig>,
+    pub console: ConsoleConfig,
+    pub serial: ConsoleConfig,
+    pub watchdog: bool,
+}
+
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct PayloadConfig {
+    pub kernel: PathBuf,
+    pub initramfs: PathBuf,
+    pub cmdline: String,
+}
+
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct CpusConfig {
+    pub boot_vcpus: u64,
+    pub max_vcpus: u64,
+}
+
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct MemoryConfig {
+    pub size: u64,
+    pub mergeable: bool,
+    pub shared: bool,
+}
+
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct DiskConfig {
+    pub path: PathBuf,
+    pub readonly: bool,
+    pub serial: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct FsConfig {
+    pub tag: String,
+    pub socket: PathBuf,
+    pub num_queues: u64,
+    pub queue_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct NetConfig {
+    pub tap: String,
+    pub num_queues: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct GpuConfig {
+    pub socket: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct ConsoleConfig {
+    pub mode: ConsoleOutputMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConsoleOutputMode {
+    Off,
+    Pty,
+    Tty,
+    File,
+    Socket,
+    Null,
+}
+
+impl ConsoleConfig {
+    pub fn mode(mode: ConsoleOutputMode) -> Self {
+        Self {
+            mode,
+            file: None,
+            socket: None,
+        }
+    }
+
+    fn to_arg(&self) -> String {
+        match (self.mode, self.file.as_ref(), self.socket.as_ref()) {
+            (ConsoleOutputMode::File, Some(file), _) => format!("file={}", file.to_string_lossy()),
+            (ConsoleOutputMode::Socket, _, Some(socket)) => {
+                format!("socket={}", socket.to_string_lossy())
+            }
+            (ConsoleOutputMode::Off, _, _) => "off".to_string(),
+            (ConsoleOutputMode::Pty, _, _) => "pty".to_string(),
+            (ConsoleOutputMode::Tty, _, _) => "tty".to_string(),
+            _ => "null".to_string(),
+        }
+    }
+}
+
+impl VmConfig {
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![
+            format!("--kernel"),
+            format!("{}", self.payload.kernel.to_string_lossy()),
+            format!("--initramfs"),
+            format!("{}", self.payload.initramfs.to_string_lossy()),
+            format!("--cmdline"),
+            format!("{}", self.payload.cmdline),
+            format!(
+                "--memory=mergeable={},shared={},size={}M",
+                on_off(self.memory.mergeable),
+                on_off(self.memory.shared),
+                self.memory.size / 1024 / 1024
+            ),
+            format!("--cpus"),
+            format!("boot={}", self.cpus.boot_vcpus),
+        ];
+        if self.watchdog {
+            args.push("--watchdog".to_string());
+        }
+        args.push("--console".to_string());
+        args.push(self.console.to_arg());
+        args.push("--serial".to_string());
+        args.push(self.serial.to_arg());
+        for gpu in self.gpu.iter() {
+            args.push("--gpu".to_string());
+            args.push(format!("socket={}", gpu.socket.to_string_lossy()));
+        }
+        if !self.fs.is_empty() {
+            args.push("--fs".to_string());
+        }
+        for fs in self.fs.iter() {
+            args.push(format!(
+                "socket={},tag={}",
+                fs.socket.to_string_lossy(),
+                fs.tag
+            ));
+        }
+        if !self.disks.is_empty() {
+            args.push("--disk".to_string());
+        }
+        for disk in self.disks.iter() {
+            args.push(format!(
+                "path={},serial={},readonly={}",
+                disk.path.to_string_lossy(),
+                disk.serial,
+                on_off(disk.readonly)
+            ));
+        }
+        for net in self.net.iter() {
+            args.push("--net".to_string());
+            args.push(format!("num_queues={},tap={}", net.num_queues, net.tap));
+        }
+        args
+    }
+}
+
+fn on_off(b: bool) -> &'static str {
+    if b {
+        "on"
+    } else {
+        "off"
+    }
+}
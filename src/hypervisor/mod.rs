@@ -0,0 +1,149 @@
+use http::Method;
+use hyper::body::Bytes;
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use crate::client::{call, json_call, read_json, RequestError};
+
+pub mod config;
+
+use config::{ConsoleConfig, DiskConfig, FsConfig, NetConfig, VmConfig};
+
+pub static API_SOCKET: &str = "cloud-hypervisor.sock";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct VmInfo {
+    pub state: VmState,
+    #[serde(default)]
+    pub config: Option<VmInfoConfig>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct VmInfoConfig {
+    pub console: Option<ConsoleConfig>,
+    pub serial: Option<ConsoleConfig>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VmState {
+    Created,
+    Running,
+    Shutdown,
+    Paused,
+    #[serde(other)]
+    Unknown,
+}
+
+impl Display for VmState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmState::Created => write!(f, "created"),
+            VmState::Running => write!(f, "running"),
+            VmState::Shutdown => write!(f, "shut down"),
+            VmState::Paused => write!(f, "paused"),
+            VmState::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiClient {
+    socket: PathBuf,
+}
+
+impl ApiClient {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Self {
+            socket: socket.into(),
+        }
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    pub async fn info(&self) -> Result<VmInfo, RequestError> {
+        let res = call(&self.socket, "/api/v1/vm.info", Method::GET, Bytes::new()).await?;
+        read_json(res).await
+    }
+
+    pub async fn create(&self, config: &VmConfig) -> Result<(), RequestError> {
+        json_call(&self.socket, "/api/v1/vm.create", Method::PUT, config).await?;
+        Ok(())
+    }
+
+    pub async fn boot(&self) -> Result<(), RequestError> {
+        self.put("vm.boot").await
+    }
+
+    pub async fn snapshot(&self, destination_url: &str) -> Result<(), RequestError> {
+        let body = json!({ "destination_url": destination_url });
+        json_call(&self.socket, "/api/v1/vm.snapshot", Method::PUT, body).await?;
+        Ok(())
+    }
+
+    pub async fn restore(&self, source_url: &str) -> Result<(), RequestError> {
+        let body = json!({ "source_url": source_url });
+        json_call(&self.socket, "/api/v1/vm.restore", Method::PUT, body).await?;
+        Ok(())
+    }
+
+    pub async fn add_disk(&self, disk: &DiskConfig) -> Result<(), RequestError> {
+        json_call(&self.socket, "/api/v1/vm.add-disk", Method::PUT, disk).await?;
+        Ok(())
+    }
+
+    pub async fn add_fs(&self, fs: &FsConfig) -> Result<(), RequestError> {
+        json_call(&self.socket, "/api/v1/vm.add-fs", Method::PUT, fs).await?;
+        Ok(())
+    }
+
+    pub async fn add_net(&self, net: &NetConfig) -> Result<(), RequestError> {
+        json_call(&self.socket, "/api/v1/vm.add-net", Method::PUT, net).await?;
+        Ok(())
+    }
+
+    pub async fn remove_device(&self, id: &str) -> Result<(), RequestError> {
+        let body = json!({ "id": id });
+        json_call(&self.socket, "/api/v1/vm.remove-device", Method::PUT, body).await?;
+        Ok(())
+    }
+
+    pub async fn pause(&self) -> Result<(), RequestError> {
+        self.put("vm.pause").await
+    }
+
+    pub async fn resume(&self) -> Result<(), RequestError> {
+        self.put("vm.resume").await
+    }
+
+    pub async fn reboot(&self) -> Result<(), RequestError> {
+        self.put("vm.reboot").await
+    }
+
+    pub async fn power_button(&self) -> Result<(), RequestError> {
+        self.put("vm.power-button").await
+    }
+
+    pub async fn shutdown(&self) -> Result<(), RequestError> {
+        self.put("vm.shutdown").await
+    }
+
+    pub async fn vmm_shutdown(&self) -> Result<(), RequestError> {
+        call(&self.socket, "/api/v1/vmm.shutdown", Method::PUT, Bytes::new()).await?;
+        Ok(())
+    }
+
+    async fn put(&self, action: &str) -> Result<(), RequestError> {
+        call(
+            &self.socket,
+            &format!("/api/v1/{}", action),
+            Method::PUT,
+            Bytes::new(),
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+use thiserror::Error;
+
+pub static INSTANCE_FILE: &str = "instance.json";
+pub static LOCK_FILE: &str = "instance.lock";
+
+#[derive(Error, Debug)]
+pub enum InstanceError {
+    #[error("runtime dir unavailable")]
+    RuntimeDirUnavailable,
+    #[error("no running vm matches \"{0}\"")]
+    NotFound(String),
+    #[error("\"{vm}\" matches several running vms: {}", .ids.join(", "))]
+    Ambiguous { vm: String, ids: Vec<String> },
+    #[error("unable to read instance info {0:?}")]
+    Io(PathBuf, #[source] io::Error),
+    #[error("invalid instance info {0:?}")]
+    Invalid(PathBuf, #[source] serde_json::Error),
+    #[error("unable to lock instance {0:?}")]
+    Lock(PathBuf, #[source] io::Error),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct InstanceInfo {
+    pub id: String,
+    pub name: Option<String>,
+    pub pid: u32,
+    #[serde(default)]
+    pub tap_device: Option<String>,
+    #[serde(default)]
+    pub started: u64,
+    #[serde(default)]
+    pub config: Option<PathBuf>,
+    #[serde(default)]
+    pub helpers: Vec<HelperInfo>,
+    #[serde(default)]
+    pub net_queues: u64,
+    #[serde(default)]
+    pub ptys: Vec<PtyInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct HelperInfo {
+    pub name: String,
+    pub pid: u32,
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct PtyInfo {
+    pub device: String,
+    pub path: PathBuf,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Instance {
+    pub dir: PathBuf,
+    pub info: InstanceInfo,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Status {
+    #[serde(rename = "running")]
+    Running,
+    #[serde(rename = "stale")]
+    Stale,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Running => write!(f, "running"),
+            Status::Stale => write!(f, "stale"),
+        }
+    }
+}
+
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    pub fn acquire(dir: &Path) -> Result<Self, InstanceError> {
+        let path = dir.join(LOCK_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| InstanceError::Lock(path.clone(), e))?;
+        if !try_lock(&file) {
+            return Err(InstanceError::Lock(path, io::Error::last_os_error()));
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+pub(crate) fn send_signal(pid: u32, signal: i32) -> io::Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(crate) fn runs_in(pid: u32, dir: &Path) -> bool {
+    let Ok(dir) = fs::canonicalize(dir) else {
+        return false;
+    };
+    fs::read_link(format!("/proc/{}/cwd", pid)).is_ok_and(|cwd| cwd == dir)
+}
+
+pub(crate) fn lock(file: &File) -> io::Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn try_lock(file: &File) -> bool {
+    unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) == 0 }
+}
+
+impl InstanceInfo {
+    pub fn write(&self, dir: &Path) -> Result<(), InstanceError> {
+        let path = dir.join(INSTANCE_FILE);
+        let temp = dir.join(format!(".{}.tmp", INSTANCE_FILE));
+        let json = serde_json::to_string_pretty(self).expect("instance info is valid json");
+        fs::write(&temp, json).map_err(|e| InstanceError::Io(temp.clone(), e))?;
+        fs::rename(&temp, &path).map_err(|e| InstanceError::Io(path, e))
+    }
+
+    pub fn read(dir: &Path) -> Result<Self, InstanceError> {
+        let path = dir.join(INSTANCE_FILE);
+        let json = fs::read_to_string(&path).map_err(|e| InstanceError::Io(path.clone(), e))?;
+        serde_json::from_str(&json).map_err(|e| InstanceError::Invalid(path, e))
+    }
+}
+
+impl Instance {
+    pub fn status(&self) -> Status {
+        match File::open(self.dir.join(LOCK_FILE)) {
+            Ok(file) if try_lock(&file) => Status::Stale,
+            Ok(_) => Status::Running,
+            Err(_) => Status::Stale,
+        }
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn instances_dir() -> Result<PathBuf, InstanceError> {
+    dirs::runtime_dir()
+        .map(|p| p.join("contain"))
+        .ok_or(InstanceError::RuntimeDirUnavailable)
+}
+
+pub fn list() -> Result<Vec<Instance>, InstanceError> {
+    let dir = instances_dir()?;
+    let read_dir = match fs::read_dir(&dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(InstanceError::Io(dir, e)),
+    };
+    let mut instances = vec![];
+    // Entries can vanish or be half torn down while we look at them, so
+    // anything unreadable is skipped instead of hiding every other vm.
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        if !path.join(INSTANCE_FILE).is_file() {
+            continue;
+        }
+        let Ok(info) = InstanceInfo::read(&path) else {
+            continue;
+        };
+        instances.push(Instance { info, dir: path });
+    }
+    instances.sort_by(|a, b| a.info.id.cmp(&b.info.id));
+    Ok(instances)
+}
+
+pub fn find(vm: &str) -> Result<Instance, InstanceError> {
+    let instances = list()?;
+
+    if let Some(instance) = instances.iter().find(|i| i.info.id == vm) {
+        return Ok(instance.clone());
+    }
+
+    let mut matches: Vec<&Instance> = instances
+        .iter()
+        .filter(|i| i.info.name.as_deref() == Some(vm))
+        .collect();
+    if matches.is_empty() {
+        matches = instances
+            .iter()
+            .filter(|i| i.info.id.starts_with(vm))
+            .collect();
+    }
+
+    match matches.as_slice() {
+        [] => Err(InstanceError::NotFound(vm.to_string())),
+        [instance] => Ok((*instance).clone()),
+        _ => Err(InstanceError::Ambiguous {
+            vm: vm.to_string(),
+            ids: matches.iter().map(|i| i.info.id.clone()).collect(),
+        }),
+    }
+}
@@ -1,4 +1,13 @@
 pub mod config;
+pub mod attach;
 pub mod run;
 pub mod daemon;
 pub mod client;
+pub mod hypervisor;
+pub mod library;
+pub mod instance;
+pub mod control;
+pub mod snapshot;
+pub mod hotplug;
+pub mod stop;
+pub mod logs;
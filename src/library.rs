@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::{fs, io};
+use thiserror::Error;
+
+use crate::config::load::{FileFormat, LoadOptions};
+use crate::config::validate::{CheckIsValidIdentifier, IdentifierValidationError};
+use crate::config::{Config, ConfigError};
+
+pub static SYSTEM_VMS_DIR: &str = "/etc/contain/vms";
+
+static EXTENSIONS: [&str; 4] = ["json", "toml", "yaml", "yml"];
+
+#[derive(Error, Debug)]
+pub enum LibraryError {
+    #[error("config dir unavailable")]
+    ConfigDirUnavailable,
+    #[error("invalid vm name")]
+    InvalidName(IdentifierValidationError),
+    #[error(
+        "no config for vm \"{name}\" found in {}",
+        .searched.iter().map(|p| format!("{:?}", p)).collect::<Vec<_>>().join(", ")
+    )]
+    NotFound { name: String, searched: Vec<PathBuf> },
+    #[error("config {path:?} is named \"{found}\" but was looked up as \"{name}\"")]
+    NameMismatch {
+        name: String,
+        found: String,
+        path: PathBuf,
+    },
+    #[error("unable to read vm library dir {0:?}")]
+    Io(PathBuf, #[source] io::Error),
+    #[error("invalid vm config")]
+    Config(#[from] ConfigError),
+    #[error("editor \"{editor}\" exited with {status}")]
+    EditorFailed { editor: String, status: ExitStatus },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+pub fn user_dir() -> Result<PathBuf, LibraryError> {
+    dirs::config_dir()
+        .map(|p| p.join("contain").join("vms"))
+        .ok_or(LibraryError::ConfigDirUnavailable)
+}
+
+pub fn search_dirs() -> Result<Vec<PathBuf>, LibraryError> {
+    Ok(vec![user_dir()?, PathBuf::from(SYSTEM_VMS_DIR)])
+}
+
+pub fn find(name: &str) -> Result<PathBuf, LibraryError> {
+    let name = name
+        .to_string()
+        .check_is_valid_identifier()
+        .map_err(LibraryError::InvalidName)?;
+    let dirs = search_dirs()?;
+    for dir in dirs.iter() {
+        for extension in EXTENSIONS {
+            let path = dir.join(format!("{}.{}", name, extension));
+            if path.is_file() {
+                return Ok(path);
+            }
+        }
+    }
+    Err(LibraryError::NotFound {
+        name,
+        searched: dirs,
+    })
+}
+
+pub fn list() -> Result<Vec<Entry>, LibraryError> {
+    let mut entries = BTreeMap::new();
+    for dir in search_dirs()?.iter().rev() {
+        let read_dir = match fs::read_dir(dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(LibraryError::Io(dir.clone(), e)),
+        };
+        for entry in read_dir {
+            let path = entry.map_err(|e| LibraryError::Io(dir.clone(), e))?.path();
+            if let Some(name) = entry_name(&path) {
+                entries.insert(name.clone(), Entry { name, path });
+            }
+        }
+    }
+    Ok(entries.into_values().collect())
+}
+
+pub fn load(name: &str, options: &LoadOptions) -> Result<Config, LibraryError> {
+    let path = find(name)?;
+    let mut config = Config::load(&path, options)?;
+    match config.name.clone() {
+        Some(found) if found != name => {
+            return Err(LibraryError::NameMismatch {
+                name: name.to_string(),
+                found,
+                path,
+            })
+        }
+        Some(_) => (),
+        None => config.name = Some(name.to_string()),
+    }
+    Ok(config)
+}
+
+pub fn locate(arg: &str) -> Result<PathBuf, LibraryError> {
+    let path = Path::new(arg);
+    if arg.contains(std::path::MAIN_SEPARATOR) || FileFormat::from_path(path).is_some() {
+        return Ok(path.to_path_buf());
+    }
+    find(arg)
+}
+
+pub fn load_path_or_name(arg: &str, options: &LoadOptions) -> Result<Config, LibraryError> {
+    let path = Path::new(arg);
+    if path.is_file() || arg.contains(std::path::MAIN_SEPARATOR) {
+        return Ok(Config::load(path, options)?);
+    }
+    load(arg, options)
+}
+
+pub fn create(name: &str, format: FileFormat) -> Result<PathBuf, LibraryError> {
+    let name = name
+        .to_string()
+        .check_is_valid_identifier()
+        .map_err(LibraryError::InvalidName)?;
+    let dir = user_dir()?;
+    let path = dir.join(format!("{}.{}", name, format));
+    let template = match format {
+        FileFormat::Json => format!("{{\n  \"name\": \"{}\"\n}}\n", name),
+        FileFormat::Toml => format!("name = \"{}\"\n", name),
+        FileFormat::Yaml => format!("name: \"{}\"\n", name),
+    };
+    fs::create_dir_all(&dir).map_err(|e| LibraryError::Io(dir.clone(), e))?;
+    fs::write(&path, template).map_err(|e| LibraryError::Io(path.clone(), e))?;
+    Ok(path)
+}
+
+fn entry_name(path: &Path) -> Option<String> {
+    if !path.is_file() || FileFormat::from_path(path).is_none() {
+        return None;
+    }
+    let name = path.file_stem()?.to_str()?.to_string();
+    name.check_is_valid_identifier().ok()
+}
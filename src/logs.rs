@@ -0,0 +1,663 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::time::sleep;
+
+use crate::config::validate::{CheckIsValidIdentifier, IdentifierValidationError};
+use crate::instance;
+
+static LOG_EXTENSION: &str = "log";
+static BOOTS_DIR: &str = "boots";
+static FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+static LINE_FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+pub static CONSOLE_LOG: &str = "console";
+pub static SERIAL_LOG: &str = "serial";
+
+#[derive(Error, Debug)]
+pub enum LogsError {
+    #[error("runtime dir unavailable")]
+    RuntimeDirUnavailable,
+    #[error("data dir unavailable")]
+    DataDirUnavailable,
+    #[error("invalid vm name")]
+    InvalidName(IdentifierValidationError),
+    #[error(
+        "no logs for vm \"{vm}\" found in {}",
+        .searched.iter().map(|p| format!("{:?}", p)).collect::<Vec<_>>().join(", ")
+    )]
+    NotFound { vm: String, searched: Vec<PathBuf> },
+    #[error("no logs for helper \"{helper}\", available are {}", .available.join(", "))]
+    UnknownHelper {
+        helper: String,
+        available: Vec<String>,
+    },
+    #[error(
+        "no boot {boot} logged for vm \"{vm}\", available are {}",
+        .available.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    UnknownBoot {
+        vm: String,
+        boot: i64,
+        available: Vec<u64>,
+    },
+    #[error(
+        "invalid time \"{0}\", use a duration like 10m, 2h or 1d or a date like 2024-01-31T12:00"
+    )]
+    InvalidSince(String),
+    #[error("unable to read logs in {0:?}")]
+    Io(PathBuf, #[source] io::Error),
+}
+
+pub fn runtime_log_dir(vm_dir: &Path) -> PathBuf {
+    vm_dir.join("logs")
+}
+
+pub fn persistent_log_dir(data_dir: &Path, name: &str) -> PathBuf {
+    data_dir.join("contain").join(name).join("logs")
+}
+
+pub fn boots_dir(data_dir: &Path, name: &str) -> PathBuf {
+    persistent_log_dir(data_dir, name).join(BOOTS_DIR)
+}
+
+pub fn find_dir(vm: &str) -> Result<PathBuf, LogsError> {
+    let vm = &check_vm(vm)?;
+    let runtime_dir = dirs::runtime_dir().ok_or(LogsError::RuntimeDirUnavailable)?;
+    let data_dir = dirs::data_dir().ok_or(LogsError::DataDirUnavailable)?;
+    let searched = vec![
+        runtime_log_dir(&runtime_dir.join("contain").join(vm)),
+        persistent_log_dir(&data_dir, vm),
+    ];
+    match searched.iter().find(|dir| dir.is_dir()) {
+        Some(dir) => Ok(dir.clone()),
+        None => Err(LogsError::NotFound {
+            vm: vm.to_string(),
+            searched,
+        }),
+    }
+}
+
+// The vm argument is joined into paths, so it must not be able to name
+// anything outside of the runtime and data dirs.
+fn check_vm(vm: &str) -> Result<String, LogsError> {
+    vm.to_string()
+        .check_is_valid_identifier()
+        .map_err(LogsError::InvalidName)
+}
+
+pub fn find_dirs(vm: &str, boot: Option<i64>) -> Result<Vec<PathBuf>, LogsError> {
+    let vm = &check_vm(vm)?;
+    let data_dir = dirs::data_dir().ok_or(LogsError::DataDirUnavailable)?;
+    let instance = instance::find(vm).ok();
+    let name = match instance.as_ref() {
+        Some(instance) => instance.info.name.clone(),
+        None => Some(vm.to_string()),
+    };
+    let latest = matches!(boot, None | Some(0));
+
+    let mut dirs = vec![];
+    let mut searched = vec![];
+    if latest {
+        match find_dir(vm) {
+            Ok(dir) => dirs.push(dir),
+            Err(LogsError::NotFound { searched: dirs, .. }) => searched.extend(dirs),
+            Err(e) => return Err(e),
+        }
+    }
+
+    let boots_dir = name.map(|name| boots_dir(&data_dir, &name));
+    let boots = match boots_dir.as_ref() {
+        Some(dir) => {
+            searched.push(dir.clone());
+            boots(dir)?
+        }
+        None => vec![],
+    };
+    match (boots_dir, boots.last(), instance) {
+        (Some(dir), Some(last), _) => {
+            let selected = match boot.unwrap_or(0) {
+                0 => Some(*last),
+                back if back < 0 => boots
+                    .len()
+                    .checked_sub(1 + back.unsigned_abs() as usize)
+                    .map(|i| boots[i]),
+                number => boots.iter().copied().find(|b| *b == number as u64),
+            };
+            match selected {
+                Some(selected) => dirs.push(dir.join(selected.to_string())),
+                None => {
+                    return Err(LogsError::UnknownBoot {
+                        vm: vm.to_string(),
+                        boot: boot.unwrap_or(0),
+                        available: boots,
+                    })
+                }
+            }
+        }
+        (_, None, Some(instance)) if latest => dirs.push(runtime_log_dir(&instance.dir)),
+        (_, None, _) if !latest => {
+            return Err(LogsError::UnknownBoot {
+                vm: vm.to_string(),
+                boot: boot.unwrap_or(0),
+                available: vec![],
+            })
+        }
+        _ => (),
+    }
+
+    dirs.retain(|dir| dir.is_dir());
+    dirs.dedup();
+    if dirs.is_empty() {
+        return Err(LogsError::NotFound {
+            vm: vm.to_string(),
+            searched,
+        });
+    }
+    Ok(dirs)
+}
+
+pub fn boots(dir: &Path) -> Result<Vec<u64>, LogsError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(LogsError::Io(dir.to_path_buf(), e)),
+    };
+    let mut boots = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|e| LogsError::Io(dir.to_path_buf(), e))?;
+        if let Some(boot) = entry.file_name().to_str().and_then(|n| n.parse().ok()) {
+            boots.push(boot);
+        }
+    }
+    boots.sort();
+    Ok(boots)
+}
+
+pub(crate) fn start_boot(dir: &Path, keep: u64) -> Result<PathBuf, LogsError> {
+    let mut boots = boots(dir)?;
+    let boot_dir = dir.join((boots.last().unwrap_or(&0) + 1).to_string());
+    fs::create_dir_all(&boot_dir).map_err(|e| LogsError::Io(boot_dir.clone(), e))?;
+    while boots.len() as u64 >= keep.max(1) {
+        let old = dir.join(boots.remove(0).to_string());
+        fs::remove_dir_all(&old).map_err(|e| LogsError::Io(old, e))?;
+    }
+    Ok(boot_dir)
+}
+
+pub fn helpers(dir: &Path) -> Result<Vec<String>, LogsError> {
+    let mut helpers = vec![];
+    for entry in fs::read_dir(dir).map_err(|e| LogsError::Io(dir.to_path_buf(), e))? {
+        let path = entry.map_err(|e| LogsError::Io(dir.to_path_buf(), e))?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(LOG_EXTENSION) {
+            continue;
+        }
+        if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+            helpers.push(stem.to_string());
+        }
+    }
+    helpers.sort();
+    Ok(helpers)
+}
+
+pub fn read(dir: &Path, helper: &str) -> Result<String, LogsError> {
+    let available = helpers(dir)?;
+    if !available.iter().any(|h| h == helper) {
+        return Err(LogsError::UnknownHelper {
+            helper: helper.to_string(),
+            available,
+        });
+    }
+    let path = log_path(dir, helper);
+    let mut content = String::new();
+    let rotations: Vec<PathBuf> = (1..)
+        .map(|rotation| rotated_path(&path, rotation))
+        .take_while(|rotation| rotation.is_file())
+        .collect();
+    for rotation in rotations.iter().rev() {
+        content.push_str(&read_file(rotation)?);
+    }
+    content.push_str(&read_file(&path)?);
+    Ok(content)
+}
+
+pub fn read_since(dir: &Path, helper: &str, since: Option<&str>) -> Result<String, LogsError> {
+    let content = read(dir, helper)?;
+    Ok(match since {
+        Some(since) if is_timestamped(helper) => filter_since(&content, since),
+        _ => content,
+    })
+}
+
+pub async fn follow(dir: &Path, helper: &str, since: Option<&str>) -> Result<(), LogsError> {
+    let path = log_path(dir, helper);
+    let open = || File::open(&path).map_err(|e| LogsError::Io(path.clone(), e));
+    let mut file = open()?;
+    let mut stdout = io::stdout();
+    let content = read_since(dir, helper, since)?;
+    let mut position = file
+        .metadata()
+        .map_err(|e| LogsError::Io(path.clone(), e))?
+        .len();
+    let mut inode = file
+        .metadata()
+        .map_err(|e| LogsError::Io(path.clone(), e))?
+        .ino();
+    write_stdout(&mut stdout, content.as_bytes(), &path)?;
+
+    loop {
+        sleep(FOLLOW_INTERVAL).await;
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        if metadata.ino() != inode || metadata.len() < position {
+            file = open()?;
+            inode = metadata.ino();
+            position = 0;
+        }
+        if metadata.len() == position {
+            continue;
+        }
+        let mut data = vec![];
+        file.seek(SeekFrom::Start(position))
+            .and_then(|_| file.read_to_end(&mut data))
+            .map_err(|e| LogsError::Io(path.clone(), e))?;
+        position += data.len() as u64;
+        write_stdout(&mut stdout, &data, &path)?;
+    }
+}
+
+fn write_stdout(stdout: &mut io::Stdout, data: &[u8], path: &Path) -> Result<(), LogsError> {
+    stdout
+        .write_all(data)
+        .and_then(|_| stdout.flush())
+        .map_err(|e| LogsError::Io(path.to_path_buf(), e))
+}
+
+fn is_timestamped(helper: &str) -> bool {
+    helper == CONSOLE_LOG || helper == SERIAL_LOG
+}
+
+fn filter_since(content: &str, since: &str) -> String {
+    content
+        .split_inclusive('\n')
+        .skip_while(|line| line.get(..TIMESTAMP_LEN).unwrap_or(line) < since)
+        .collect()
+}
+
+pub fn parse_since(since: &str) -> Result<String, LogsError> {
+    parse_since_at(since, SystemTime::now())
+}
+
+fn parse_since_at(since: &str, now: SystemTime) -> Result<String, LogsError> {
+    let invalid = || LogsError::InvalidSince(since.to_string());
+    if let Some(unit) = since.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+        let amount: u64 = since[..since.len() - 1].parse().map_err(|_| invalid())?;
+        let seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let time = now
+            .checked_sub(Duration::from_secs(amount.saturating_mul(seconds)))
+            .ok_or_else(invalid)?;
+        return Ok(timestamp(time));
+    }
+
+    let since = since.replacen(' ', "T", 1);
+    let template = "0000-00-00T00:00:00";
+    let valid = [10, 16, 19].contains(&since.len())
+        && since.chars().zip(template.chars()).all(|(c, t)| match t {
+            '0' => c.is_ascii_digit(),
+            t => c == t,
+        });
+    if !valid {
+        return Err(invalid());
+    }
+    Ok(since)
+}
+
+const TIMESTAMP_LEN: usize = "0000-00-00T00:00:00.000Z".len();
+
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u64;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u64;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn read_file(path: &Path) -> Result<String, LogsError> {
+    let bytes = fs::read(path).map_err(|e| LogsError::Io(path.to_path_buf(), e))?;
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+pub fn log_path(dir: &Path, helper: &str) -> PathBuf {
+    dir.join(format!("{}.{}", helper, LOG_EXTENSION))
+}
+
+fn rotated_path(path: &Path, rotation: u64) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(format!(".{}", rotation));
+    path.into()
+}
+
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    rotations: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: PathBuf, max_size: u64, rotations: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            rotations,
+        })
+    }
+
+    pub fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.append(line)
+    }
+
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.rotations == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for rotation in (1..self.rotations).rev() {
+                let from = rotated_path(&self.path, rotation);
+                if from.is_file() {
+                    fs::rename(from, rotated_path(&self.path, rotation + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+pub struct TimestampedLog {
+    pending: Arc<Mutex<PendingLine>>,
+}
+
+struct PendingLine {
+    file: RotatingFile,
+    data: Vec<u8>,
+    at_line_start: bool,
+    continues_line: bool,
+    written: Instant,
+}
+
+impl TimestampedLog {
+    pub fn open(path: PathBuf, max_size: u64, rotations: u64) -> io::Result<Self> {
+        let pending = Arc::new(Mutex::new(PendingLine {
+            file: RotatingFile::open(path, max_size, rotations)?,
+            data: vec![],
+            at_line_start: true,
+            continues_line: false,
+            written: Instant::now(),
+        }));
+
+        let weak = Arc::downgrade(&pending);
+        thread::spawn(move || loop {
+            thread::sleep(LINE_FLUSH_INTERVAL);
+            let Some(pending) = weak.upgrade() else {
+                return;
+            };
+            let mut pending = pending.lock().unwrap();
+            if pending.written.elapsed() >= LINE_FLUSH_INTERVAL {
+                _ = pending.flush();
+            }
+        });
+
+        Ok(Self { pending })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.written = Instant::now();
+        for byte in data {
+            if *byte == b'\r' {
+                continue;
+            }
+            if pending.at_line_start {
+                pending.at_line_start = false;
+                let stamp = format!("{} ", timestamp(SystemTime::now()));
+                pending.data.extend_from_slice(stamp.as_bytes());
+            }
+            pending.data.push(*byte);
+            if *byte == b'\n' {
+                pending.at_line_start = true;
+                pending.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PendingLine {
+    fn flush(&mut self) -> io::Result<()> {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+        // The rest of a line that was flushed early is appended without
+        // rotating, so it is never split from its timestamp.
+        if self.continues_line {
+            self.file.append(&self.data)?;
+        } else {
+            self.file.write_line(&self.data)?;
+        }
+        self.data.clear();
+        self.continues_line = !self.at_line_start;
+        Ok(())
+    }
+}
+
+impl Drop for TimestampedLog {
+    fn drop(&mut self) {
+        _ = self.pending.lock().unwrap().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempLog(PathBuf);
+
+    impl TempLog {
+        fn new(test: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "contain-log-{}-{}.log",
+                test,
+                std::process::id()
+            ));
+            _ = fs::remove_file(&path);
+            Self(path)
+        }
+
+        fn open(&self) -> TimestampedLog {
+            TimestampedLog::open(self.0.clone(), 1024 * 1024, 1).unwrap()
+        }
+
+        fn read(&self) -> String {
+            fs::read_to_string(&self.0).unwrap_or_default()
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn lines_are_stamped_when_they_start() {
+        let temp = TempLog::new("start");
+        let mut log = temp.open();
+        let started = SystemTime::now();
+
+        log.write(b"booting").unwrap();
+        thread::sleep(Duration::from_millis(1100));
+        log.write(b" done\r\n").unwrap();
+
+        let content = temp.read();
+        assert_eq!(&content[TIMESTAMP_LEN..], " booting done\n");
+        assert!(content[..TIMESTAMP_LEN] <= *timestamp(started + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn partial_lines_are_flushed_when_idle() {
+        let temp = TempLog::new("idle");
+        let mut log = temp.open();
+
+        log.write(b"first\nlogin: ").unwrap();
+        assert!(temp.read().ends_with(" first\n"));
+        thread::sleep(LINE_FLUSH_INTERVAL * 3);
+        assert!(temp.read().ends_with(" login: "));
+
+        log.write(b"root\n").unwrap();
+        let content = temp.read();
+        let lines: Vec<&str> = content.lines().map(|l| &l[TIMESTAMP_LEN..]).collect();
+        assert_eq!(lines, [" first", " login: root"]);
+    }
+
+    #[test]
+    fn pending_bytes_are_flushed_on_drop() {
+        let temp = TempLog::new("drop");
+        let mut log = temp.open();
+
+        log.write(b"partial").unwrap();
+        drop(log);
+
+        assert_eq!(&temp.read()[TIMESTAMP_LEN..], " partial");
+    }
+
+    #[test]
+    fn days_map_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(civil_from_days(47541), (2100, 3, 1));
+        assert_eq!(civil_from_days(-135081), (1600, 2, 29));
+    }
+
+    #[test]
+    fn timestamps_are_utc_with_millis() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_millis(951_782_400_123)),
+            "2000-02-29T00:00:00.123Z"
+        );
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_secs(1_000_000)),
+            "1970-01-12T13:46:40.000Z"
+        );
+    }
+
+    #[test]
+    fn relative_since_counts_back_from_now() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let ago = |seconds| timestamp(now - Duration::from_secs(seconds));
+        assert_eq!(parse_since_at("30s", now).unwrap(), ago(30));
+        assert_eq!(parse_since_at("5m", now).unwrap(), ago(5 * 60));
+        assert_eq!(parse_since_at("2h", now).unwrap(), ago(2 * 60 * 60));
+        assert_eq!(parse_since_at("1d", now).unwrap(), ago(24 * 60 * 60));
+    }
+
+    #[test]
+    fn absolute_since_is_normalized() {
+        let now = UNIX_EPOCH;
+        assert_eq!(parse_since_at("2024-01-01", now).unwrap(), "2024-01-01");
+        assert_eq!(
+            parse_since_at("2024-01-01 10:30", now).unwrap(),
+            "2024-01-01T10:30"
+        );
+        assert_eq!(
+            parse_since_at("2024-01-01T10:30:15", now).unwrap(),
+            "2024-01-01T10:30:15"
+        );
+    }
+
+    #[test]
+    fn malformed_since_is_rejected() {
+        for since in [
+            "",
+            "m",
+            "10x",
+            "-5m",
+            "1.5h",
+            "2024-1-1",
+            "2024-01-01T1:00",
+            "2024/01/01",
+            "yesterday",
+        ] {
+            assert!(
+                matches!(parse_since_at(since, SystemTime::now()), Err(LogsError::InvalidSince(s)) if s == since),
+                "{:?} should be rejected",
+                since
+            );
+        }
+    }
+
+    #[test]
+    fn vm_names_cannot_leave_the_log_dirs() {
+        for vm in ["..", ".", "../other", "a/b", ""] {
+            assert!(matches!(find_dir(vm), Err(LogsError::InvalidName(_))));
+            assert!(matches!(
+                find_dirs(vm, None),
+                Err(LogsError::InvalidName(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, post};
+use axum::{http::StatusCode, Json, Router};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use tokio::net::UnixListener;
+use tokio::sync::watch;
+use tokio::task::spawn_blocking;
+
+use crate::config::filesystem::Share;
+use crate::instance::HelperInfo;
+use crate::run::launcher::Launcher;
+use crate::run::plan::VmPlan;
+use crate::run::probe::probe;
+use crate::run::support::{wait_for_sockets, InstanceRecord, Readiness, SupportHandle};
+use crate::run::{describe, VmError};
+
+pub static RUNNER_SOCKET: &str = "runner.sock";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ShareHelperRequest {
+    pub share: Share,
+}
+
+#[derive(Clone)]
+pub(crate) struct RunnerState {
+    pub(crate) plan: VmPlan,
+    pub(crate) launcher: Arc<dyn Launcher>,
+    pub(crate) handles: Arc<Mutex<Vec<SupportHandle>>>,
+    pub(crate) record: Arc<InstanceRecord>,
+    pub(crate) shutdown_rx: watch::Receiver<bool>,
+}
+
+pub(crate) async fn serve(listener: UnixListener, state: RunnerState) -> std::io::Result<()> {
+    axum::serve(listener, root(state)).await
+}
+
+fn root(state: RunnerState) -> Router {
+    Router::new().nest("/api", api()).with_state(state)
+}
+
+fn api() -> Router<RunnerState> {
+    Router::new()
+        .route("/helpers/share", post(share_start))
+        .route("/helpers/{name}", delete(helper_stop))
+}
+
+async fn share_start(
+    State(state): State<RunnerState>,
+    Json(req): Json<ShareHelperRequest>,
+) -> Response {
+    match start_share(&state, &req.share).await {
+        Ok(helper) => (StatusCode::CREATED, Json(helper)).into_response(),
+        Err(e @ VmError::SupportProcessAlreadyRunning(_)) => {
+            (StatusCode::CONFLICT, describe(&e)).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, describe(&e)).into_response(),
+    }
+}
+
+async fn start_share(state: &RunnerState, share: &Share) -> Result<HelperInfo, VmError> {
+    let plan = &state.plan;
+    let support = plan.share_support(share);
+    if state
+        .handles
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|handle| handle.name == support.name)
+    {
+        return Err(VmError::SupportProcessAlreadyRunning(support.name));
+    }
+    probe(state.launcher.as_ref(), &plan.virtiofsd).await?;
+
+    for dir in plan.logs.dirs.iter() {
+        fs::create_dir_all(dir).map_err(|e| VmError::FailedToCreateLogDir(dir.clone(), e))?;
+    }
+    _ = fs::remove_file(plan.dir.join(&support.socket));
+    let handle = SupportHandle::start(state.launcher.as_ref(), &support, &plan.dir, &plan.logs)?;
+    let starting = Mutex::new(vec![handle]);
+    let mut shutdown_rx = state.shutdown_rx.clone();
+    let ready = wait_for_sockets(&starting, &plan.dir, plan.socket_timeout, &mut shutdown_rx).await;
+    let handle = starting.into_inner().unwrap().remove(0);
+    let ready = match ready {
+        Ok(Readiness::Ready) => Ok(()),
+        Ok(Readiness::ShutdownRequested) => Err(VmError::ShuttingDown),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = ready {
+        _ = handle.process.kill();
+        _ = spawn_blocking(move || handle.process.wait()).await;
+        return Err(e);
+    }
+
+    let helper = HelperInfo {
+        name: handle.name.clone(),
+        pid: handle.process.id(),
+        socket: Some(plan.dir.join(&handle.socket)),
+    };
+    state.handles.lock().unwrap().push(handle);
+    state.record.update_helpers(|helpers| helpers.push(helper.clone()));
+    Ok(helper)
+}
+
+async fn helper_stop(State(state): State<RunnerState>, Path(name): Path<String>) -> StatusCode {
+    if state
+        .plan
+        .support
+        .iter()
+        .any(|support| support.name == name)
+    {
+        return StatusCode::FORBIDDEN;
+    }
+    let handle = {
+        let mut handles = state.handles.lock().unwrap();
+        let Some(i) = handles.iter().position(|handle| handle.name == name) else {
+            return StatusCode::NOT_FOUND;
+        };
+        handles.remove(i)
+    };
+    state.record.update_helpers(|helpers| {
+        helpers.retain(|h| h.name != name)
+    });
+
+    let socket = state.plan.dir.join(&handle.socket);
+    _ = handle.process.kill();
+    let waited = spawn_blocking(move || handle.process.wait()).await;
+    _ = fs::remove_file(socket);
+    match waited {
+        Ok(Ok(_)) => StatusCode::ACCEPTED,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
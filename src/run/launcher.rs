@@ -0,0 +1,332 @@
+use shared_child::SharedChild;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StdioMode {
+    Null,
+    PipedStdout,
+    PipedOutput,
+    Inherit,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Invocation {
+    pub command: Vec<String>,
+    pub dir: PathBuf,
+    pub stdio: StdioMode,
+    pub sockets: Vec<PathBuf>,
+}
+
+pub trait Process: Send + Sync {
+    fn id(&self) -> u32;
+    fn kill(&self) -> io::Result<()>;
+    fn wait(&self) -> io::Result<ExitStatus>;
+    fn try_wait(&self) -> io::Result<Option<ExitStatus>>;
+    fn take_stdout(&self) -> Option<Box<dyn Read + Send>>;
+    fn take_stderr(&self) -> Option<Box<dyn Read + Send>>;
+}
+
+pub trait Launcher: Send + Sync {
+    fn launch(&self, invocation: &Invocation) -> io::Result<Arc<dyn Process>>;
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct SystemLauncher;
+
+impl Launcher for SystemLauncher {
+    fn launch(&self, invocation: &Invocation) -> io::Result<Arc<dyn Process>> {
+        let mut iter = invocation.command.iter();
+        let program = iter
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+        let mut command = Command::new(program);
+        command.args(iter).current_dir(&invocation.dir);
+        match invocation.stdio {
+            StdioMode::Null => {
+                command
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null());
+            }
+            StdioMode::PipedStdout => {
+                command
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::null());
+            }
+            StdioMode::PipedOutput => {
+                command
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped());
+            }
+            StdioMode::Inherit => (),
+        }
+        Ok(Arc::new(SharedChild::spawn(&mut command)?))
+    }
+}
+
+impl Process for SharedChild {
+    fn id(&self) -> u32 {
+        SharedChild::id(self)
+    }
+    fn kill(&self) -> io::Result<()> {
+        SharedChild::kill(self)
+    }
+    fn wait(&self) -> io::Result<ExitStatus> {
+        SharedChild::wait(self)
+    }
+    fn try_wait(&self) -> io::Result<Option<ExitStatus>> {
+        SharedChild::try_wait(self)
+    }
+    fn take_stdout(&self) -> Option<Box<dyn Read + Send>> {
+        SharedChild::take_stdout(self).map(|s| Box::new(s) as Box<dyn Read + Send>)
+    }
+    fn take_stderr(&self) -> Option<Box<dyn Read + Send>> {
+        SharedChild::take_stderr(self).map(|s| Box::new(s) as Box<dyn Read + Send>)
+    }
+}
+
+/// A [`Launcher`] that spawns nothing and records what it was asked to run.
+///
+/// This is the supported fake for driving [`execute`](crate::run::execute)
+/// and the probes without real binaries. Programs behave as set through
+/// [`set_behavior`](fake::RecordingLauncher::set_behavior) and report
+/// [`FAKE_VERSION`](fake::FAKE_VERSION) when asked for their version unless
+/// told otherwise. Enable the `test-util` feature to use it outside this crate.
+#[cfg(any(test, feature = "test-util"))]
+pub mod fake {
+    use std::collections::{HashMap, HashSet};
+    use std::fs;
+    use std::io::{self, Read};
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Invocation, Launcher, Process};
+
+    const SIGKILL: i32 = 9;
+
+    pub static FAKE_VERSION: &str = "999.0.0";
+
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub enum Behavior {
+        /// Create the invocation's sockets and run until killed.
+        Run,
+        /// Run until killed without creating any sockets.
+        RunWithoutSockets,
+        /// Create the sockets and exit with the code after the duration.
+        RunFor(Duration, i32),
+        /// Exit with the code right away.
+        Exit(i32),
+        /// Fail to spawn with `NotFound`.
+        FailToSpawn,
+    }
+
+    /// What happened to the launched processes, in order.
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub enum Event {
+        Launched(Invocation),
+        Killed(Vec<String>),
+    }
+
+    #[derive(Default)]
+    pub struct RecordingLauncher {
+        behaviors: Mutex<HashMap<String, Behavior>>,
+        versions: Mutex<HashMap<String, Option<String>>>,
+        hanging_versions: Mutex<HashSet<String>>,
+        stderr: Mutex<HashMap<String, String>>,
+        events: Arc<Mutex<Vec<Event>>>,
+        next_id: Mutex<u32>,
+    }
+
+    impl RecordingLauncher {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn set_behavior(&self, program: &str, behavior: Behavior) {
+            self.behaviors
+                .lock()
+                .unwrap()
+                .insert(program.to_string(), behavior);
+        }
+
+        pub fn set_version_output(&self, program: &str, output: Option<&str>) {
+            self.versions
+                .lock()
+                .unwrap()
+                .insert(program.to_string(), output.map(str::to_string));
+        }
+
+        pub fn set_version_hangs(&self, program: &str) {
+            self.hanging_versions
+                .lock()
+                .unwrap()
+                .insert(program.to_string());
+        }
+
+        pub fn set_stderr_output(&self, program: &str, output: &str) {
+            self.stderr
+                .lock()
+                .unwrap()
+                .insert(program.to_string(), output.to_string());
+        }
+
+        pub fn events(&self) -> Vec<Event> {
+            self.events.lock().unwrap().clone()
+        }
+
+        pub fn invocations(&self) -> Vec<Invocation> {
+            self.events()
+                .into_iter()
+                .filter_map(|e| match e {
+                    Event::Launched(invocation) => Some(invocation),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    impl Launcher for RecordingLauncher {
+        fn launch(&self, invocation: &Invocation) -> io::Result<Arc<dyn Process>> {
+            let program = invocation.command.first().cloned().unwrap_or_default();
+            let behavior = self
+                .behaviors
+                .lock()
+                .unwrap()
+                .get(&program)
+                .cloned()
+                .unwrap_or(Behavior::Run);
+
+            if behavior == Behavior::FailToSpawn {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} is set up to fail", program),
+                ));
+            }
+
+            let stdout = if matches!(invocation.command.as_slice(), [_, arg] if arg == "--version") {
+                let output = self
+                    .versions
+                    .lock()
+                    .unwrap()
+                    .get(&program)
+                    .cloned()
+                    .unwrap_or_else(|| Some(format!("{} {}", program, FAKE_VERSION)));
+                let status = match output {
+                    _ if self.hanging_versions.lock().unwrap().contains(&program) => None,
+                    Some(_) => Some(0),
+                    None => Some(1),
+                };
+                Some((output.unwrap_or_default(), status))
+            } else {
+                None
+            };
+
+            self.events
+                .lock()
+                .unwrap()
+                .push(Event::Launched(invocation.clone()));
+
+            let exit_code = match (&stdout, &behavior) {
+                (Some((_, code)), _) => *code,
+                (None, Behavior::Exit(code)) => Some(*code),
+                _ => None,
+            };
+            let status = match exit_code {
+                Some(code) => Some(ExitStatus::from_raw(code << 8)),
+                None if behavior == Behavior::RunWithoutSockets => None,
+                None => {
+                    for socket in invocation.sockets.iter() {
+                        fs::write(invocation.dir.join(socket), [])?;
+                    }
+                    None
+                }
+            };
+
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+
+            let process = Arc::new(FakeProcess {
+                id: *next_id,
+                command: invocation.command.clone(),
+                stdout: Mutex::new(stdout.map(|(output, _)| output)),
+                stderr: Mutex::new(self.stderr.lock().unwrap().get(&program).cloned()),
+                events: self.events.clone(),
+                status: Mutex::new(status),
+                exited: Condvar::new(),
+            });
+
+            if let (Behavior::RunFor(duration, code), None) = (behavior, process.try_wait()?) {
+                let process = process.clone();
+                thread::spawn(move || {
+                    thread::sleep(duration);
+                    process.exit(ExitStatus::from_raw(code << 8));
+                });
+            }
+
+            Ok(process)
+        }
+    }
+
+    struct FakeProcess {
+        id: u32,
+        command: Vec<String>,
+        stdout: Mutex<Option<String>>,
+        stderr: Mutex<Option<String>>,
+        events: Arc<Mutex<Vec<Event>>>,
+        status: Mutex<Option<ExitStatus>>,
+        exited: Condvar,
+    }
+
+    impl FakeProcess {
+        fn exit(&self, exit_status: ExitStatus) -> bool {
+            let mut status = self.status.lock().unwrap();
+            if status.is_some() {
+                return false;
+            }
+            *status = Some(exit_status);
+            self.exited.notify_all();
+            true
+        }
+    }
+
+    impl Process for FakeProcess {
+        fn id(&self) -> u32 {
+            self.id
+        }
+        fn kill(&self) -> io::Result<()> {
+            if self.exit(ExitStatus::from_raw(SIGKILL)) {
+                self.events
+                    .lock()
+                    .unwrap()
+                    .push(Event::Killed(self.command.clone()));
+            }
+            Ok(())
+        }
+        fn wait(&self) -> io::Result<ExitStatus> {
+            let status = self
+                .exited
+                .wait_while(self.status.lock().unwrap(), |s| s.is_none())
+                .unwrap();
+            Ok(status.expect("waited until the process exited"))
+        }
+        fn try_wait(&self) -> io::Result<Option<ExitStatus>> {
+            Ok(*self.status.lock().unwrap())
+        }
+        fn take_stdout(&self) -> Option<Box<dyn Read + Send>> {
+            let stdout = self.stdout.lock().unwrap().take().unwrap_or_default();
+            Some(Box::new(io::Cursor::new(stdout.into_bytes())))
+        }
+        fn take_stderr(&self) -> Option<Box<dyn Read + Send>> {
+            let stderr = self.stderr.lock().unwrap().take().unwrap_or_default();
+            Some(Box::new(io::Cursor::new(stderr.into_bytes())))
+        }
+    }
+}
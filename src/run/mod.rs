@@ -0,0 +1,1273 @@
+use qcow2_rs::error::Qcow2Error;
+use qcow2_rs::meta::Qcow2Header;
+use rand::Rng;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{env, fs, io, thread};
+use thiserror::Error;
+use tokio::net::UnixListener;
+use tokio::select;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, Instant};
+
+use crate::attach::{self, Pty, Sink, CONSOLE_SOCKET, SERIAL_SOCKET};
+use crate::client::{delete_tap_device, request_tap_device, RequestError};
+use crate::config::validate::{IdentifierValidationError, Report};
+use crate::config::*;
+use crate::hotplug;
+use crate::hypervisor::config::{ConsoleConfig, ConsoleOutputMode};
+use crate::hypervisor::{ApiClient, VmState};
+use crate::instance::{
+    self, HelperInfo, InstanceError, InstanceInfo, InstanceLock, PtyInfo, INSTANCE_FILE,
+};
+use crate::logs::{
+    self, log_path, runtime_log_dir, LogsError, TimestampedLog, CONSOLE_LOG, SERIAL_LOG,
+};
+use crate::snapshot::{self, SnapshotError};
+
+pub mod api;
+pub mod launcher;
+pub mod plan;
+pub mod probe;
+mod support;
+
+use api::{RunnerState, RUNNER_SOCKET};
+use launcher::{Invocation, Launcher, Process, SystemLauncher};
+use plan::{plan_vm, DiskCreation, Environment, VmPlan};
+use probe::{probe, Version};
+use support::{supervise, wait_for_sockets, InstanceRecord, Readiness, SupportHandle};
+
+static API_TIMEOUT: Duration = Duration::from_secs(5);
+static RUNNER_LOG: &str = "runner";
+
+#[derive(Error, Debug)]
+pub enum VmError {
+    #[error("invalid config:\n{0}")]
+    InvalidConfig(Report),
+
+    #[error("cannot read environment variable USER to determine current user")]
+    UserEnvUnavailable(env::VarError),
+    #[error("cannot read environment variable WAYLAND_DISPLAY to determine wayland socket")]
+    WaylandSocketEnvUnavailable(env::VarError),
+
+    #[error("runtime dir unavailable")]
+    RuntimeDirUnavailable,
+    #[error("current dir unavailable")]
+    CurrentDirUnavailable(io::Error),
+    #[error("data dir unavailable")]
+    DataDirUnavailable,
+
+    #[error("wayland socket unavailable")]
+    WaylandSocketUnavailable(Option<io::Error>),
+
+    #[error("error while taking to daemon")]
+    DaemonRequest(#[from] RequestError),
+
+    #[error("failed to spawn vm process")]
+    FailedToSpawnVMProcess(io::Error),
+    #[error("failed to kill vm process")]
+    FailedToKillVMProcess(io::Error),
+    #[error("failed to wait on vm process")]
+    FailedToWaitOnVMProcess(io::Error),
+
+    #[error("vm process exited with {0} before the vm was booted")]
+    VmProcessExited(ExitStatus),
+    #[error("failed to check for hypervisor api socket")]
+    FailedToCheckForApiSocket(io::Error),
+    #[error("hypervisor did not create its api socket within {}s", .0.as_secs())]
+    ApiSocketTimeout(Duration),
+    #[error("failed to create vm through the hypervisor api")]
+    FailedToCreateVm(#[source] RequestError),
+    #[error("failed to boot vm through the hypervisor api")]
+    FailedToBootVm(#[source] RequestError),
+
+    #[error("failed to look up the console pty through the hypervisor api")]
+    FailedToFindConsolePty(#[source] RequestError),
+    #[error("hypervisor did not report a console pty")]
+    ConsolePtyUnavailable,
+    #[error("failed to open console pty {0:?}")]
+    FailedToOpenConsolePty(PathBuf, #[source] io::Error),
+
+    #[error("failed to restore vm through the hypervisor api")]
+    FailedToRestoreVm(#[source] RequestError),
+    #[error("failed to resume restored vm")]
+    FailedToResumeVm(#[source] RequestError),
+    #[error("unable to find snapshot")]
+    Snapshot(#[source] SnapshotError),
+    #[error("snapshot was taken of vm {snapshot}, not {}", .config.as_deref().unwrap_or("this unnamed vm"))]
+    SnapshotOfOtherVm {
+        snapshot: String,
+        config: Option<String>,
+    },
+    #[error("vm {0} is already running")]
+    AlreadyRunning(String),
+
+    #[error("failed to spawn detached runner")]
+    FailedToSpawnRunner(#[source] io::Error),
+    #[error("detached runner exited with {status} before the vm was running, see {log:?}")]
+    RunnerExited { status: ExitStatus, log: PathBuf },
+    #[error("vm was not running within {}s, see {log:?}", .timeout.as_secs())]
+    RunnerTimeout { timeout: Duration, log: PathBuf },
+
+    #[error("failed to spawn support process")]
+    FailedToSpawnSupportProcess(io::Error),
+    #[error("failed to kill support process")]
+    FailedToKillSupportProcess(io::Error),
+    #[error("failed to wait on support process")]
+    FailedToWaitOnSupportProcess(io::Error),
+
+    #[error("failed to create runtime dir")]
+    FailedToCreateRuntimeDir(io::Error),
+    #[error("failed to delete runtime dir")]
+    FailedToDeleteRuntimeDir(io::Error),
+    #[error(
+        "failed to clean up vm:{}",
+        .0.iter().map(|e| format!("\n  {}", describe(e))).collect::<String>()
+    )]
+    Cleanup(Vec<VmError>),
+    #[error("failed to write instance info")]
+    FailedToWriteInstanceInfo(#[source] InstanceError),
+    #[error("failed to lock runtime dir")]
+    FailedToLockRuntimeDir(#[source] InstanceError),
+
+    #[error("failed to resolve disk location")]
+    FailedToResolveDiskLocation,
+
+    #[error("failed to create log dir {0:?}")]
+    FailedToCreateLogDir(PathBuf, #[source] io::Error),
+    #[error("failed to open log file {0:?}")]
+    FailedToOpenLogFile(PathBuf, #[source] io::Error),
+    #[error("failed to start console logs of a new boot")]
+    FailedToStartBootLog(#[source] LogsError),
+
+    #[error("failed to check for support socket")]
+    FailedToCheckForSupportSocket(io::Error),
+    #[error(
+        "support process {name} exited before its socket was ready with {status}{}",
+        if stderr.is_empty() { String::new() } else { format!(", stderr:\n{}", stderr) }
+    )]
+    SupportProcessExited {
+        name: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+    #[error(
+        "support processes {} did not create their sockets within {}s",
+        .missing.join(", "),
+        .timeout.as_secs()
+    )]
+    SupportSocketTimeout {
+        timeout: Duration,
+        missing: Vec<String>,
+    },
+    #[error("support process {0} is already running")]
+    SupportProcessAlreadyRunning(String),
+    #[error("vm is shutting down")]
+    ShuttingDown,
+    #[error("failed to listen on the runner socket")]
+    FailedToBindRunnerSocket(#[source] io::Error),
+
+    #[error("invalid kernel path")]
+    InvalidKernelPath(Option<io::Error>),
+    #[error("invalid initrd path")]
+    InvalidInitRDPath(Option<io::Error>),
+
+    #[error("invalid share tag")]
+    InvalidShareTag(IdentifierValidationError),
+    #[error("invalid share source")]
+    InvalidShareSource(Option<io::Error>),
+
+    #[error("invalid disk tag")]
+    InvalidDiskTag(IdentifierValidationError),
+    #[error("invalid disk source")]
+    InvalidDiskSource(Option<io::Error>),
+
+    #[error("failed to create disk")]
+    FailedToCreateDisk(Qcow2Error),
+    #[error("memory size of {0}M is too large")]
+    MemoryTooLarge(u64),
+    #[error("disk size of {0}M is too large")]
+    DiskTooLarge(u64),
+    #[error("creating {0} disks is not supported")]
+    UnsupportedDiskCreation(filesystem::Format),
+
+    #[error("invalid binaries defaults file")]
+    InvalidBinaryDefaults(ConfigError),
+    #[error("{name} binary {path:?} is missing or cannot be run")]
+    BinaryMissing {
+        name: String,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("unable to determine version of {name} binary {path:?} from output \"{output}\"")]
+    BinaryVersionUnknown {
+        name: String,
+        path: PathBuf,
+        output: String,
+    },
+    #[error("{name} binary {path:?} did not report its version within {timeout:?}")]
+    BinaryProbeTimeout {
+        name: String,
+        path: PathBuf,
+        timeout: Duration,
+    },
+    #[error("{name} binary {path:?} has version {found} but at least {minimum} is required")]
+    BinaryTooOld {
+        name: String,
+        path: PathBuf,
+        found: Version,
+        minimum: Version,
+    },
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RunOptions {
+    pub restore: Option<String>,
+    pub config_path: Option<PathBuf>,
+    pub id: Option<String>,
+    pub detached: bool,
+}
+
+pub async fn run_vm(config: Config, options: &RunOptions) -> Result<(), VmError> {
+    let plan = dry_run(&config, options)?;
+    execute(plan, Arc::new(SystemLauncher)).await
+}
+
+pub fn dry_run(config: &Config, options: &RunOptions) -> Result<VmPlan, VmError> {
+    let report = config.validate();
+    if !report.is_ok() {
+        return Err(VmError::InvalidConfig(report));
+    }
+
+    let env = Environment::from_host()?;
+    let snapshot = match options.restore.as_deref() {
+        Some(restore) => Some(
+            snapshot::find(config.name.as_deref(), restore).map_err(VmError::Snapshot)?,
+        ),
+        None => None,
+    };
+    if let Some(snapshot) = snapshot.as_ref() {
+        if config.name.as_deref() != Some(snapshot.info.vm_name.as_str()) {
+            return Err(VmError::SnapshotOfOtherVm {
+                snapshot: snapshot.info.vm_name.clone(),
+                config: config.name.clone(),
+            });
+        }
+    }
+    let vm_id = match (snapshot.as_ref(), options.id.as_ref()) {
+        (Some(snapshot), _) => snapshot.info.vm_id.clone(),
+        (None, Some(id)) => id.clone(),
+        (None, None) => hex::encode(rand::rng().random::<[u8; 16]>()),
+    };
+
+    let mut plan = plan_vm(config, &env, &vm_id)?;
+    plan.config_path = options.config_path.clone();
+    plan.detached = options.detached;
+    if let Some(snapshot) = snapshot {
+        plan.restore = Some(snapshot.url());
+        if let Some(network) = plan.network.as_mut() {
+            network.name = snapshot.info.tap_device;
+        }
+    }
+    Ok(plan)
+}
+
+pub async fn run_detached(
+    config: &Config,
+    options: &RunOptions,
+    runner: impl FnOnce(&str) -> Command,
+) -> Result<VmPlan, VmError> {
+    let options = RunOptions {
+        detached: true,
+        ..options.clone()
+    };
+    let plan = dry_run(config, &options)?;
+    if plan.dir.join(INSTANCE_FILE).exists() {
+        return Err(VmError::AlreadyRunning(plan.id.clone()));
+    }
+
+    let log_dir = plan.logs.dirs.last().unwrap_or(&plan.dir);
+    let log = log_path(log_dir, RUNNER_LOG);
+    let open_log = || {
+        fs::create_dir_all(log_dir)?;
+        OpenOptions::new().create(true).append(true).open(&log)
+    };
+    let stdout = open_log().map_err(|e| VmError::FailedToOpenLogFile(log.clone(), e))?;
+    let stderr = stdout
+        .try_clone()
+        .map_err(|e| VmError::FailedToOpenLogFile(log.clone(), e))?;
+
+    let mut command = runner(&plan.id);
+    command
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(stderr);
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().map_err(VmError::FailedToSpawnRunner)?;
+
+    let api = ApiClient::new(plan.dir.join(&plan.api_socket));
+    let deadline = plan.socket_timeout + API_TIMEOUT;
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().map_err(VmError::FailedToSpawnRunner)? {
+            return Err(VmError::RunnerExited { status, log });
+        }
+        if started.elapsed() >= deadline {
+            return Err(VmError::RunnerTimeout {
+                timeout: deadline,
+                log,
+            });
+        }
+        if api.socket().exists() {
+            if let Ok(Ok(info)) = timeout(API_TIMEOUT, api.info()).await {
+                if info.state == VmState::Running {
+                    return Ok(plan);
+                }
+            }
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+pub async fn execute(plan: VmPlan, launcher: Arc<dyn Launcher>) -> Result<(), VmError> {
+    let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+    let shutdown_tx_clone = shutdown_tx.clone();
+
+    tokio::spawn(async move {
+        let mut terminate = signal(SignalKind::terminate()).unwrap();
+        let mut interrupt = signal(SignalKind::interrupt()).unwrap();
+        let mut quit = signal(SignalKind::quit()).unwrap();
+        let mut hangup = signal(SignalKind::hangup()).unwrap();
+        select! {
+            _ = terminate.recv() => {},
+            _ = interrupt.recv() => {},
+            _ = quit.recv() => {},
+            _ = hangup.recv() => {},
+        }
+        shutdown_tx_clone
+            .send(true)
+            .expect("sending shutdown signal should work");
+    });
+
+    if let Some(wayland_socket) = plan.wayland_socket.as_ref() {
+        wayland_socket
+            .try_exists()
+            .map_failure(VmError::WaylandSocketUnavailable)?;
+    }
+
+    for binary in plan.binaries.iter() {
+        probe(launcher.as_ref(), binary).await?;
+    }
+
+    let vm_dir = plan.dir.clone();
+    if vm_dir.join(INSTANCE_FILE).exists() {
+        return Err(VmError::AlreadyRunning(plan.id.clone()));
+    }
+    fs::create_dir_all(vm_dir.clone()).map_err(VmError::FailedToCreateRuntimeDir)?;
+
+    let lock = match InstanceLock::acquire(&vm_dir) {
+        Ok(lock) => lock,
+        Err(e) => {
+            _ = fs::remove_dir_all(&vm_dir);
+            return Err(VmError::FailedToLockRuntimeDir(e));
+        }
+    };
+
+    let info = InstanceInfo {
+        id: plan.id.clone(),
+        name: plan.name.clone(),
+        pid: std::process::id(),
+        tap_device: None,
+        started: instance::unix_time(),
+        config: plan.config_path.clone(),
+        helpers: vec![],
+        net_queues: plan.cpus,
+        ptys: vec![],
+    };
+    let record = match InstanceRecord::create(&vm_dir, info) {
+        Ok(record) => Arc::new(record),
+        Err(e) => {
+            _ = fs::remove_dir_all(&vm_dir);
+            return Err(VmError::FailedToWriteInstanceInfo(e));
+        }
+    };
+
+    let mut running = Running {
+        _lock: lock,
+        record,
+        api: ApiClient::new(vm_dir.join(&plan.api_socket)),
+        grace_period: plan.grace_period,
+        vm_dir,
+        name: plan.name.clone(),
+        tap_device_name: None,
+        support_processes: Arc::new(Mutex::new(vec![])),
+        supervisor: None,
+        control: None,
+        consoles: vec![],
+        vm_process: None,
+    };
+    let result = running
+        .start(&plan, launcher, shutdown_tx, &mut shutdown_rx)
+        .await;
+    let teardown = running.teardown().await;
+    result.and(teardown)
+}
+
+struct Running {
+    _lock: InstanceLock,
+    record: Arc<InstanceRecord>,
+    api: ApiClient,
+    grace_period: Duration,
+    vm_dir: PathBuf,
+    name: Option<String>,
+    tap_device_name: Option<String>,
+    support_processes: Arc<Mutex<Vec<SupportHandle>>>,
+    supervisor: Option<JoinHandle<()>>,
+    control: Option<JoinHandle<io::Result<()>>>,
+    consoles: Vec<JoinHandle<io::Result<()>>>,
+    vm_process: Option<Arc<dyn Process>>,
+}
+
+impl Running {
+    async fn start(
+        &mut self,
+        plan: &VmPlan,
+        launcher: Arc<dyn Launcher>,
+        shutdown_tx: watch::Sender<bool>,
+        shutdown_rx: &mut watch::Receiver<bool>,
+    ) -> Result<(), VmError> {
+        let vm_dir = self.vm_dir.clone();
+
+        if let Some(network) = plan.network.as_ref() {
+            let name = request_tap_device(network.user.clone(), network.name.clone()).await?;
+            self.tap_device_name = Some(name.clone());
+            self.record
+                .update(|info| info.tap_device = Some(name))
+                .map_err(VmError::FailedToWriteInstanceInfo)?;
+        }
+
+        for disk in plan.disks.iter().filter(|_| plan.restore.is_none()) {
+            let Some(creation) = disk.create.as_ref() else {
+                continue;
+            };
+            if disk
+                .path
+                .try_exists()
+                .map_err(|e| VmError::InvalidDiskSource(Some(e)))?
+            {
+                continue;
+            }
+            create_disk(&disk.path, creation)?;
+        }
+
+        if !plan.support.is_empty() || plan.captures_console() {
+            for dir in plan.logs.dirs.iter() {
+                fs::create_dir_all(dir)
+                    .map_err(|e| VmError::FailedToCreateLogDir(dir.clone(), e))?;
+            }
+        }
+        let console_logs = match (plan.captures_console(), plan.logs.boots.as_ref()) {
+            (true, Some(boots)) => logs::start_boot(boots, plan.logs.keep_boots)
+                .map_err(VmError::FailedToStartBootLog)?,
+            _ => runtime_log_dir(&vm_dir),
+        };
+        let consoles = plan.opens_ptys().then_some(console_logs.as_path());
+
+        for support in plan.support.iter() {
+            let handle = SupportHandle::start(launcher.as_ref(), support, &vm_dir, &plan.logs)?;
+            self.support_processes.lock().unwrap().push(handle);
+        }
+        self.record_helpers(plan)?;
+
+        match wait_for_sockets(
+            &self.support_processes,
+            &vm_dir,
+            plan.socket_timeout,
+            shutdown_rx,
+        )
+        .await?
+        {
+            Readiness::Ready => (),
+            Readiness::ShutdownRequested => return Ok(()),
+        }
+
+        self.supervisor = Some(tokio::spawn(supervise(
+            self.support_processes.clone(),
+            self.record.clone(),
+            plan.clone(),
+            launcher.clone(),
+            shutdown_tx.clone(),
+        )));
+
+        let listener = UnixListener::bind(vm_dir.join(RUNNER_SOCKET))
+            .map_err(VmError::FailedToBindRunnerSocket)?;
+        self.control = Some(tokio::spawn(api::serve(
+            listener,
+            RunnerState {
+                plan: plan.clone(),
+                launcher: launcher.clone(),
+                handles: self.support_processes.clone(),
+                record: self.record.clone(),
+                shutdown_rx: shutdown_rx.clone(),
+            },
+        )));
+
+        let vm_cmd = plan.vm_command(self.tap_device_name.as_deref());
+
+        let vm_process_arc = launcher
+            .launch(&Invocation {
+                command: vm_cmd,
+                dir: vm_dir.clone(),
+                stdio: plan.stdio(),
+                sockets: vec![],
+            })
+            .map_err(VmError::FailedToSpawnVMProcess)?;
+        self.vm_process = Some(vm_process_arc.clone());
+        self.record_helpers(plan)?;
+
+        let vm_process_arc_clone = vm_process_arc.clone();
+        _ = thread::spawn(move || {
+            _ = vm_process_arc_clone.wait();
+            shutdown_tx.send(true)
+        });
+
+        let boot_through_api = plan.boot == hypervisor::Boot::Api || plan.restore.is_some();
+        if (boot_through_api || consoles.is_some())
+            && !self
+                .wait_for_api(vm_process_arc.as_ref(), plan.socket_timeout, shutdown_rx)
+                .await?
+        {
+            return Ok(());
+        }
+        if boot_through_api {
+            self.boot(plan, consoles).await?;
+        } else if let Some(console_logs) = consoles {
+            self.capture_console(plan, console_logs).await?;
+        }
+
+        _ = shutdown_rx.wait_for(|b| *b).await;
+
+        Ok(())
+    }
+
+    fn record_helpers(&self, plan: &VmPlan) -> Result<(), VmError> {
+        let mut helpers: Vec<HelperInfo> = self
+            .support_processes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|handle| HelperInfo {
+                name: handle.name.clone(),
+                pid: handle.process.id(),
+                socket: Some(self.vm_dir.join(&handle.socket)),
+            })
+            .collect();
+        if let Some(vm_process) = self.vm_process.as_ref() {
+            helpers.push(HelperInfo {
+                name: "cloud-hypervisor".to_string(),
+                pid: vm_process.id(),
+                socket: Some(self.vm_dir.join(&plan.api_socket)),
+            });
+        }
+        self.record
+            .update(|info| info.helpers = helpers)
+            .map_err(VmError::FailedToWriteInstanceInfo)
+    }
+
+    async fn wait_for_api(
+        &self,
+        vm_process: &dyn Process,
+        timeout: Duration,
+        shutdown_rx: &watch::Receiver<bool>,
+    ) -> Result<bool, VmError> {
+        let started = Instant::now();
+        loop {
+            if *shutdown_rx.borrow() {
+                return Ok(false);
+            }
+            if let Some(status) = vm_process
+                .try_wait()
+                .map_err(VmError::FailedToWaitOnVMProcess)?
+            {
+                return Err(VmError::VmProcessExited(status));
+            }
+            if self
+                .api
+                .socket()
+                .try_exists()
+                .map_err(VmError::FailedToCheckForApiSocket)?
+            {
+                break;
+            }
+            if started.elapsed() >= timeout {
+                return Err(VmError::ApiSocketTimeout(timeout));
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        Ok(true)
+    }
+
+    async fn boot(&mut self, plan: &VmPlan, console_logs: Option<&Path>) -> Result<(), VmError> {
+        match plan.restore.as_deref() {
+            Some(source_url) => {
+                if plan.boot == hypervisor::Boot::Api {
+                    self.api
+                        .restore(source_url)
+                        .await
+                        .map_err(VmError::FailedToRestoreVm)?;
+                }
+                if let Some(console_logs) = console_logs {
+                    self.capture_console(plan, console_logs).await?;
+                }
+                self.api.resume().await.map_err(VmError::FailedToResumeVm)?;
+            }
+            None => {
+                let vm_config = plan.vm_config(self.tap_device_name.as_deref());
+                self.api
+                    .create(&vm_config)
+                    .await
+                    .map_err(VmError::FailedToCreateVm)?;
+                if let Some(console_logs) = console_logs {
+                    self.capture_console(plan, console_logs).await?;
+                }
+                self.api.boot().await.map_err(VmError::FailedToBootVm)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn capture_console(&mut self, plan: &VmPlan, log_dir: &Path) -> Result<(), VmError> {
+        let info = self.api.info().await.map_err(VmError::FailedToFindConsolePty)?;
+        let config = info.config.ok_or(VmError::ConsolePtyUnavailable)?;
+        let log = |name: &str| -> Result<Sink, VmError> {
+            let path = log_path(log_dir, name);
+            let mut log =
+                TimestampedLog::open(path.clone(), plan.logs.max_size, plan.logs.rotations)
+                    .map_err(|e| VmError::FailedToOpenLogFile(path, e))?;
+            Ok(Box::new(move |data| _ = log.write(data)))
+        };
+
+        for (name, device, pty, socket) in [
+            (CONSOLE_LOG, &plan.console, config.console, CONSOLE_SOCKET),
+            (SERIAL_LOG, &plan.serial, config.serial, SERIAL_SOCKET),
+        ] {
+            let path = pty_path(pty);
+            if device.sink == console::Sink::Pty {
+                if let Some(path) = path {
+                    eprintln!("{} pty: {}", name, path.display());
+                    self.record
+                        .update(|info| {
+                            info.ptys.push(PtyInfo {
+                                device: name.to_string(),
+                                path,
+                            })
+                        })
+                        .map_err(VmError::FailedToWriteInstanceInfo)?;
+                }
+                continue;
+            }
+            if !plan.captures(device) {
+                continue;
+            }
+            let path = path.ok_or(VmError::ConsolePtyUnavailable)?;
+            let mut sinks = vec![log(name)?];
+            if device.sink == console::Sink::Log && !plan.detached {
+                sinks.push(Box::new(|data| {
+                    let mut stdout = io::stdout().lock();
+                    _ = stdout.write_all(data);
+                    _ = stdout.flush();
+                }));
+            }
+            let pty =
+                Pty::open(&path, sinks).map_err(|e| VmError::FailedToOpenConsolePty(path, e))?;
+            if device.sink == console::Sink::Socket {
+                self.consoles
+                    .push(tokio::spawn(attach::serve(self.vm_dir.join(socket), pty)));
+            }
+        }
+        Ok(())
+    }
+
+    async fn teardown(self) -> Result<(), VmError> {
+        if let Some(control) = self.control {
+            control.abort();
+            _ = control.await;
+        }
+
+        if let Some(supervisor) = self.supervisor {
+            supervisor.abort();
+            _ = supervisor.await;
+        }
+
+        for console in self.consoles {
+            console.abort();
+            _ = console.await;
+        }
+
+        let mut errors = vec![];
+        if let Some(vm_process) = self.vm_process.as_ref() {
+            if let Err(e) = shutdown_vm(vm_process.as_ref(), &self.api, self.grace_period).await {
+                errors.push(e);
+            }
+        }
+
+        let support_processes = std::mem::take(&mut *self.support_processes.lock().unwrap());
+
+        for support in support_processes.iter() {
+            if let Err(e) = support.process.kill() {
+                errors.push(VmError::FailedToKillSupportProcess(e));
+            }
+        }
+
+        for support in support_processes.iter() {
+            if let Err(e) = support.process.wait() {
+                errors.push(VmError::FailedToWaitOnSupportProcess(e));
+            }
+        }
+
+        if let Err(e) = hotplug::release_all(&self.vm_dir).await {
+            eprintln!("failed to read hotplugged devices: {}", e);
+        }
+
+        if let Some(name) = self.tap_device_name {
+            let snapshotted = (self.name.as_deref())
+                .is_some_and(|vm_name| snapshot::uses_tap_device(vm_name, &name));
+            if let Err(e) = delete_tap_device(name, snapshotted).await {
+                errors.push(e.into());
+            }
+        }
+
+        if let Err(e) = fs::remove_dir_all(self.vm_dir) {
+            errors.push(VmError::FailedToDeleteRuntimeDir(e));
+        }
+
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(VmError::Cleanup(errors)),
+        }
+    }
+}
+
+pub(crate) fn describe(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        message.push_str(&format!(": {}", e));
+        source = e.source();
+    }
+    message
+}
+
+async fn shutdown_vm(
+    process: &dyn Process,
+    api: &ApiClient,
+    grace_period: Duration,
+) -> Result<(), VmError> {
+    if wait_for_exit(process, Duration::ZERO).await? {
+        return Ok(());
+    }
+
+    if let Ok(Ok(())) = timeout(API_TIMEOUT, api.power_button()).await {
+        if wait_for_exit(process, grace_period).await? {
+            return Ok(());
+        }
+        eprintln!(
+            "vm did not power off within {}s, shutting it down",
+            grace_period.as_secs()
+        );
+    }
+
+    if let Ok(Ok(())) = timeout(API_TIMEOUT, api.shutdown()).await {
+        // vm.shutdown only stops the guest, the vmm keeps running until it is
+        // told to exit, and may do so before it answers.
+        _ = timeout(API_TIMEOUT, api.vmm_shutdown()).await;
+        if wait_for_exit(process, API_TIMEOUT).await? {
+            return Ok(());
+        }
+    }
+
+    process.kill().map_err(VmError::FailedToKillVMProcess)?;
+    process.wait().map_err(VmError::FailedToWaitOnVMProcess)?;
+
+    Ok(())
+}
+
+fn pty_path(console: Option<ConsoleConfig>) -> Option<PathBuf> {
+    console
+        .filter(|console| console.mode == ConsoleOutputMode::Pty)
+        .and_then(|console| console.file)
+}
+
+async fn wait_for_exit(process: &dyn Process, duration: Duration) -> Result<bool, VmError> {
+    let deadline = Instant::now() + duration;
+    loop {
+        if process
+            .try_wait()
+            .map_err(VmError::FailedToWaitOnVMProcess)?
+            .is_some()
+        {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+pub(crate) fn create_disk(path: &Path, disk: &DiskCreation) -> Result<(), VmError> {
+    if disk.format != filesystem::Format::Qcow2 {
+        return Err(VmError::UnsupportedDiskCreation(disk.format.clone()));
+    }
+    let size = disk
+        .size
+        .checked_mul(1024 * 1024)
+        .ok_or(VmError::DiskTooLarge(disk.size))?;
+
+    if let Some(parrent) = path.parent() {
+        fs::create_dir_all(parrent).map_err(|e| VmError::InvalidDiskSource(Some(e)))?;
+    }
+    let mut file =
+        std::fs::File::create(path).map_err(|e| VmError::InvalidDiskSource(Some(e)))?;
+
+    let cluster_bits = 16;
+    let refcount_order = 4;
+    let bs_shift = 9_u8;
+    let bs = 1 << bs_shift;
+    let (rc_t, rc_b, _) =
+        Qcow2Header::calculate_meta_params(size, cluster_bits, refcount_order, bs);
+    let clusters = 1 + rc_t.1 + rc_b.1;
+    let img_size = ((clusters as usize) << cluster_bits) + 512;
+    let mut buf = vec![0u8; img_size];
+    Qcow2Header::format_qcow2(&mut buf, size, cluster_bits, refcount_order, bs)
+        .map_err(VmError::FailedToCreateDisk)?;
+    file.write_all(&buf)
+        .map_err(|e| VmError::InvalidDiskSource(Some(e)))?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct Resolved {
+    pub shares: Vec<ResolvedShare>,
+    pub disks: Vec<ResolvedDisk>,
+    pub tap_device: bool,
+    pub virtio_gpu: bool,
+}
+
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct ResolvedShare {
+    pub tag: String,
+    pub source: PathBuf,
+    pub socket: PathBuf,
+}
+
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct ResolvedDisk {
+    pub tag: String,
+    pub path: PathBuf,
+    pub exists: bool,
+    pub create: bool,
+}
+
+pub fn resolve(config: &Config) -> Result<Resolved, VmError> {
+    let plan = plan_vm(config, &Environment::from_host()?, "resolve")?;
+    Resolved::from_plan(&plan)
+}
+
+impl Resolved {
+    pub fn from_plan(plan: &VmPlan) -> Result<Self, VmError> {
+        let shares = plan
+            .shares
+            .iter()
+            .map(|share| ResolvedShare {
+                tag: share.tag.clone(),
+                source: share.source.clone(),
+                socket: share.socket.clone(),
+            })
+            .collect();
+
+        let mut disks = vec![];
+        for disk in plan.disks.iter() {
+            let exists = disk
+                .path
+                .try_exists()
+                .map_err(|e| VmError::InvalidDiskSource(Some(e)))?;
+            disks.push(ResolvedDisk {
+                tag: disk.serial.clone(),
+                path: disk.path.clone(),
+                exists,
+                create: disk.create.is_some(),
+            });
+        }
+
+        Ok(Self {
+            shares,
+            disks,
+            tap_device: plan.network.is_some(),
+            virtio_gpu: plan.gpu_socket.is_some(),
+        })
+    }
+}
+
+trait MapFailure<T, E, M: Fn(Option<E>) -> T> {
+    fn map_failure(self, m: M) -> Result<(), T>;
+}
+
+impl<T, E, M: Fn(Option<E>) -> T> MapFailure<T, E, M> for Result<bool, E> {
+    fn map_failure(self, m: M) -> Result<(), T> {
+        match self {
+            Ok(b) => {
+                if b {
+                    Ok(())
+                } else {
+                    Err(m(None))
+                }
+            }
+            Err(e) => Err(m(Some(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{start_share_helper, stop_helper};
+    use crate::config::binaries::Binaries;
+    use crate::config::filesystem::Share;
+    use crate::config::support::Policy;
+    use crate::run::launcher::fake::{Behavior, Event, RecordingLauncher};
+
+    struct TempRoot(PathBuf);
+
+    impl Drop for TempRoot {
+        fn drop(&mut self) {
+            _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn setup(test: &str, shares: &[&str], gpu: bool) -> (VmPlan, TempRoot) {
+        let root = env::temp_dir().join(format!("contain-{}-{}", test, std::process::id()));
+        _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("run")).unwrap();
+        fs::write(root.join("run").join("wayland-1"), []).unwrap();
+        let env = Environment {
+            runtime_dir: root.join("run"),
+            data_dir: root.join("data"),
+            current_dir: root.clone(),
+            user: Some("user".to_string()),
+            wayland_display: Some("wayland-1".to_string()),
+            binaries: Binaries::default(),
+        };
+        let mut config = Config {
+            kernel_path: "kernel".into(),
+            initrd_path: "initrd".into(),
+            ..Default::default()
+        };
+        config.hypervisor.boot = hypervisor::Boot::Argv;
+        config.graphics.virtio_gpu = gpu;
+        config.filesystem.shares = shares
+            .iter()
+            .map(|tag| Share {
+                source: "src".into(),
+                tag: tag.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let mut plan = plan_vm(&config, &env, "vm").unwrap();
+        plan.socket_timeout = Duration::from_millis(300);
+        plan.grace_period = Duration::from_millis(100);
+        (plan, TempRoot(root))
+    }
+
+    fn launched(launcher: &RecordingLauncher) -> Vec<String> {
+        launcher
+            .invocations()
+            .into_iter()
+            .filter(|invocation| invocation.command.get(1).map(String::as_str) != Some("--version"))
+            .map(|invocation| invocation.command[0].clone())
+            .collect()
+    }
+
+    fn killed(launcher: &RecordingLauncher) -> Vec<String> {
+        launcher
+            .events()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Killed(command) => Some(command[0].clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn helper_without_socket_times_out() {
+        let (plan, _root) = setup("no-socket", &["a"], false);
+        let dir = plan.dir.clone();
+        let launcher = Arc::new(RecordingLauncher::new());
+        launcher.set_behavior("virtiofsd", Behavior::RunWithoutSockets);
+
+        let result = execute(plan, launcher.clone()).await;
+
+        match result {
+            Err(VmError::SupportSocketTimeout { missing, timeout }) => {
+                assert_eq!(missing, ["virtiofs-a"]);
+                assert_eq!(timeout, Duration::from_millis(300));
+            }
+            other => panic!("expected a socket timeout, got {:?}", other),
+        }
+        assert_eq!(launched(&launcher), ["virtiofsd"]);
+        assert_eq!(killed(&launcher), ["virtiofsd"]);
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn helper_exiting_early_reports_its_stderr() {
+        let (plan, _root) = setup("exits-early", &["a"], false);
+        let dir = plan.dir.clone();
+        let launcher = Arc::new(RecordingLauncher::new());
+        launcher.set_behavior("virtiofsd", Behavior::Exit(1));
+        launcher.set_stderr_output("virtiofsd", "shared dir does not exist\n");
+
+        let result = execute(plan, launcher.clone()).await;
+
+        match result {
+            Err(VmError::SupportProcessExited {
+                name,
+                status,
+                stderr,
+            }) => {
+                assert_eq!(name, "virtiofs-a");
+                assert_eq!(status.code(), Some(1));
+                assert_eq!(stderr, "shared dir does not exist");
+            }
+            other => panic!("expected an early exit, got {:?}", other),
+        }
+        assert_eq!(launched(&launcher), ["virtiofsd"]);
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn failing_binary_probe_starts_nothing() {
+        let (plan, _root) = setup("probe", &["a"], false);
+        let launcher = Arc::new(RecordingLauncher::new());
+        launcher.set_version_output("virtiofsd", Some("virtiofsd 1.0.0"));
+
+        let result = execute(plan, launcher.clone()).await;
+
+        assert!(matches!(result, Err(VmError::BinaryTooOld { .. })));
+        assert!(launched(&launcher).is_empty());
+    }
+
+    #[tokio::test]
+    async fn vm_exit_tears_down_helpers() {
+        let (plan, _root) = setup("vm-exit", &["a"], false);
+        let dir = plan.dir.clone();
+        let launcher = Arc::new(RecordingLauncher::new());
+        launcher.set_behavior(
+            "cloud-hypervisor",
+            Behavior::RunFor(Duration::from_millis(200), 0),
+        );
+
+        execute(plan, launcher.clone()).await.unwrap();
+
+        assert_eq!(launched(&launcher), ["virtiofsd", "cloud-hypervisor"]);
+        assert_eq!(killed(&launcher), ["virtiofsd"]);
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn helper_exit_stops_the_vm_before_the_other_helpers() {
+        let (plan, _root) = setup("teardown-order", &["a"], true);
+        let dir = plan.dir.clone();
+        let launcher = Arc::new(RecordingLauncher::new());
+        launcher.set_behavior("crosvm", Behavior::RunFor(Duration::from_millis(300), 1));
+
+        execute(plan, launcher.clone()).await.unwrap();
+
+        assert_eq!(
+            launched(&launcher),
+            ["virtiofsd", "crosvm", "cloud-hypervisor"]
+        );
+        assert_eq!(killed(&launcher), ["cloud-hypervisor", "virtiofsd"]);
+        assert!(!dir.exists());
+    }
+
+    async fn start_with_runner(
+        plan: VmPlan,
+        launcher: Arc<RecordingLauncher>,
+    ) -> (JoinHandle<Result<(), VmError>>, PathBuf) {
+        let runner = plan.dir.join(RUNNER_SOCKET);
+        let running = tokio::spawn(execute(plan, launcher));
+        while !runner.exists() {
+            sleep(Duration::from_millis(10)).await;
+        }
+        (running, runner)
+    }
+
+    fn share(tag: &str) -> Share {
+        Share {
+            source: "/srv/share".into(),
+            tag: tag.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn runner_starts_and_stops_hotplugged_helpers() {
+        let (mut plan, _root) = setup("hotplug-share", &[], false);
+        plan.virtiofsd.path = "/opt/pinned/virtiofsd".into();
+        let dir = plan.dir.clone();
+        let launcher = Arc::new(RecordingLauncher::new());
+        launcher.set_behavior(
+            "cloud-hypervisor",
+            Behavior::RunFor(Duration::from_millis(500), 0),
+        );
+        let (running, runner) = start_with_runner(plan, launcher.clone()).await;
+
+        let helper = start_share_helper(&runner, share("b")).await.unwrap();
+        assert_eq!(helper.name, "virtiofs-b");
+        assert!(dir.join("virtio-fs-b.sock").exists());
+        let helpers = InstanceInfo::read(&dir).unwrap().helpers;
+        assert!(helpers
+            .iter()
+            .any(|h| h.name == "virtiofs-b" && h.pid == helper.pid));
+        assert!(start_share_helper(&runner, share("b")).await.is_err());
+
+        stop_helper(&runner, "virtiofs-b").await.unwrap();
+        assert_eq!(killed(&launcher), ["/opt/pinned/virtiofsd"]);
+        assert!(!dir.join("virtio-fs-b.sock").exists());
+        let helpers = InstanceInfo::read(&dir).unwrap().helpers;
+        assert!(helpers.iter().all(|h| h.name != "virtiofs-b"));
+
+        running.await.unwrap().unwrap();
+        assert_eq!(
+            launched(&launcher),
+            ["cloud-hypervisor", "/opt/pinned/virtiofsd"]
+        );
+    }
+
+    #[tokio::test]
+    async fn runner_refuses_to_stop_configured_helpers() {
+        let (plan, _root) = setup("hotplug-configured", &["a"], false);
+        let launcher = Arc::new(RecordingLauncher::new());
+        launcher.set_behavior(
+            "cloud-hypervisor",
+            Behavior::RunFor(Duration::from_millis(300), 0),
+        );
+        let (running, runner) = start_with_runner(plan, launcher.clone()).await;
+
+        assert!(stop_helper(&runner, "virtiofs-a").await.is_err());
+        assert!(stop_helper(&runner, "virtiofs-missing").await.is_err());
+
+        running.await.unwrap().unwrap();
+        assert_eq!(killed(&launcher), ["virtiofsd"]);
+    }
+
+    #[tokio::test]
+    async fn hotplugged_helpers_are_torn_down_with_the_vm() {
+        let (plan, _root) = setup("hotplug-teardown", &[], false);
+        let dir = plan.dir.clone();
+        let launcher = Arc::new(RecordingLauncher::new());
+        launcher.set_behavior(
+            "cloud-hypervisor",
+            Behavior::RunFor(Duration::from_millis(300), 0),
+        );
+        let (running, runner) = start_with_runner(plan, launcher.clone()).await;
+
+        start_share_helper(&runner, share("b")).await.unwrap();
+        running.await.unwrap().unwrap();
+
+        assert_eq!(killed(&launcher), ["virtiofsd"]);
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn restarts_and_hotplugs_are_both_recorded() {
+        let (mut plan, _root) = setup("record", &["a"], false);
+        plan.support[0].policy = Policy::Restart;
+        plan.virtiofsd.path = "/opt/pinned/virtiofsd".into();
+        let dir = plan.dir.clone();
+        let launcher = Arc::new(RecordingLauncher::new());
+        launcher.set_behavior("virtiofsd", Behavior::RunFor(Duration::from_millis(200), 1));
+        launcher.set_behavior(
+            "cloud-hypervisor",
+            Behavior::RunFor(Duration::from_millis(800), 0),
+        );
+        let (running, runner) = start_with_runner(plan, launcher.clone()).await;
+        launcher.set_behavior("virtiofsd", Behavior::Run);
+        let pid = |name: &str| {
+            InstanceInfo::read(&dir)
+                .unwrap()
+                .helpers
+                .into_iter()
+                .find(|h| h.name == name)
+                .map(|h| h.pid)
+        };
+        let first = pid("virtiofs-a").unwrap();
+
+        let hotplugged = start_share_helper(&runner, share("b")).await.unwrap();
+        sleep(Duration::from_millis(400)).await;
+
+        assert_ne!(pid("virtiofs-a"), Some(first));
+        assert_eq!(pid("virtiofs-b"), Some(hotplugged.pid));
+        assert!(pid("cloud-hypervisor").is_some());
+        running.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn rejected_disks_are_not_created() {
+        let path = env::temp_dir().join(format!("contain-disk-{}.img", std::process::id()));
+        let raw = DiskCreation {
+            size: 1,
+            format: filesystem::Format::Raw,
+        };
+        assert!(matches!(
+            create_disk(&path, &raw),
+            Err(VmError::UnsupportedDiskCreation(filesystem::Format::Raw))
+        ));
+        let huge = DiskCreation {
+            size: u64::MAX,
+            format: filesystem::Format::Qcow2,
+        };
+        assert!(matches!(
+            create_disk(&path, &huge),
+            Err(VmError::DiskTooLarge(u64::MAX))
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn resolved_matches_the_plan() {
+        let (plan, root) = setup("resolved", &["home"], true);
+        let resolved = Resolved::from_plan(&plan).unwrap();
+        assert_eq!(
+            resolved.shares,
+            [ResolvedShare {
+                tag: "home".to_string(),
+                source: root.0.join("src"),
+                socket: plan.shares[0].socket.clone(),
+            }]
+        );
+        assert!(resolved.disks.is_empty());
+        assert!(resolved.virtio_gpu);
+        assert!(!resolved.tap_device);
+    }
+}
@@ -0,0 +1,1155 @@
+use serde_json::json;
+use std::env;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::attach::{CONSOLE_SOCKET, SERIAL_SOCKET};
+use crate::config::binaries::Binaries;
+use crate::config::validate::CheckIsValidIdentifier;
+use crate::config::*;
+use crate::hypervisor::config::{
+    ConsoleConfig, ConsoleOutputMode, CpusConfig, DiskConfig, FsConfig, GpuConfig, MemoryConfig,
+    NetConfig, PayloadConfig, VmConfig,
+};
+use crate::hypervisor::API_SOCKET;
+use crate::logs::{boots_dir, persistent_log_dir, runtime_log_dir};
+use crate::run::launcher::StdioMode;
+use crate::run::probe::Version;
+use crate::run::VmError;
+
+pub static CLOUD_HYPERVISOR_MINIMUM_VERSION: Version = Version(40, 0, 0);
+pub static VIRTIOFSD_MINIMUM_VERSION: Version = Version(1, 11, 0);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Environment {
+    pub runtime_dir: PathBuf,
+    pub data_dir: PathBuf,
+    pub current_dir: PathBuf,
+    pub user: Option<String>,
+    pub wayland_display: Option<String>,
+    pub binaries: Binaries,
+}
+
+impl Environment {
+    pub fn from_host() -> Result<Self, VmError> {
+        Ok(Self {
+            runtime_dir: dirs::runtime_dir().ok_or(VmError::RuntimeDirUnavailable)?,
+            data_dir: dirs::data_dir().ok_or(VmError::DataDirUnavailable)?,
+            current_dir: env::current_dir().map_err(VmError::CurrentDirUnavailable)?,
+            user: env::var("USER").ok(),
+            wayland_display: env::var("WAYLAND_DISPLAY").ok(),
+            binaries: Binaries::user_defaults().map_err(VmError::InvalidBinaryDefaults)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VmPlan {
+    pub id: String,
+    pub name: Option<String>,
+    pub dir: PathBuf,
+    pub kernel: PathBuf,
+    pub initrd: PathBuf,
+    pub cmdline: String,
+    pub cpus: u64,
+    pub memory: u64,
+    pub console: console::Device,
+    pub serial: console::Device,
+    pub detached: bool,
+    pub hypervisor: PathBuf,
+    pub api_socket: PathBuf,
+    pub boot: hypervisor::Boot,
+    pub restore: Option<String>,
+    pub config_path: Option<PathBuf>,
+    pub grace_period: Duration,
+    pub binaries: Vec<PlannedBinary>,
+    pub support: Vec<SupportProcess>,
+    pub support_config: support::Support,
+    pub virtiofsd: PlannedBinary,
+    pub socket_timeout: Duration,
+    pub max_restarts: u64,
+    pub logs: LogPlan,
+    pub shares: Vec<PlannedShare>,
+    pub disks: Vec<PlannedDisk>,
+    pub gpu_socket: Option<PathBuf>,
+    pub wayland_socket: Option<PathBuf>,
+    pub network: Option<NetworkRequest>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlannedBinary {
+    pub name: String,
+    pub path: PathBuf,
+    pub minimum: Option<Version>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SupportProcess {
+    pub name: String,
+    pub command: Vec<String>,
+    pub socket: PathBuf,
+    pub policy: support::Policy,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogPlan {
+    pub dirs: Vec<PathBuf>,
+    pub max_size: u64,
+    pub rotations: u64,
+    pub boots: Option<PathBuf>,
+    pub keep_boots: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlannedShare {
+    pub tag: String,
+    pub source: PathBuf,
+    pub socket: PathBuf,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlannedDisk {
+    pub serial: String,
+    pub path: PathBuf,
+    pub readonly: bool,
+    pub create: Option<DiskCreation>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DiskCreation {
+    pub size: u64,
+    pub format: filesystem::Format,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NetworkRequest {
+    pub user: String,
+    pub queues: u64,
+    pub name: Option<String>,
+}
+
+pub fn plan_vm(config: &Config, env: &Environment, id: &str) -> Result<VmPlan, VmError> {
+    let dir = env.runtime_dir.join("contain").join(id);
+    let data_dir = env.data_dir.join("contain");
+
+    let binaries = config.binaries.or(&env.binaries);
+    let binary_path = |path: PathBuf| {
+        if path.components().count() > 1 {
+            env.current_dir.join(path)
+        } else {
+            path
+        }
+    };
+    let hypervisor = binary_path(binaries.cloud_hypervisor());
+    let virtiofsd = binary_path(binaries.virtiofsd());
+    let crosvm = binary_path(binaries.crosvm());
+
+    let mut planned_binaries = vec![PlannedBinary {
+        name: "cloud-hypervisor".to_string(),
+        path: hypervisor.clone(),
+        minimum: Some(CLOUD_HYPERVISOR_MINIMUM_VERSION),
+    }];
+    let virtiofsd = PlannedBinary {
+        name: "virtiofsd".to_string(),
+        path: virtiofsd,
+        minimum: Some(VIRTIOFSD_MINIMUM_VERSION),
+    };
+    if !config.filesystem.shares.is_empty() {
+        planned_binaries.push(virtiofsd.clone());
+    }
+    if config.graphics.virtio_gpu {
+        planned_binaries.push(PlannedBinary {
+            name: "crosvm".to_string(),
+            path: crosvm.clone(),
+            minimum: None,
+        });
+    }
+
+    let mut support = vec![];
+    let mut shares = vec![];
+
+    for share in config.filesystem.shares.iter() {
+        let tag = share
+            .tag
+            .clone()
+            .check_is_valid_identifier()
+            .map_err(VmError::InvalidShareTag)?;
+        let source = env.current_dir.join(&share.source);
+        let helper = share_support(&virtiofsd.path, &config.support, &tag, &source, share);
+        shares.push(PlannedShare {
+            tag,
+            source,
+            socket: helper.socket.clone(),
+        });
+        support.push(helper);
+    }
+
+    let mut disks = vec![];
+    for disk in config.filesystem.disks.iter() {
+        let serial = disk
+            .tag
+            .clone()
+            .check_is_valid_identifier()
+            .map_err(VmError::InvalidDiskTag)?;
+
+        let path = disk
+            .resolve_path(&config.name, &data_dir)
+            .ok_or(VmError::FailedToResolveDiskLocation)?;
+
+        disks.push(PlannedDisk {
+            serial,
+            path: env.current_dir.join(path),
+            readonly: !disk.write,
+            create: disk.create.then(|| DiskCreation {
+                size: disk.size,
+                format: disk.format.clone(),
+            }),
+        });
+    }
+
+    let (gpu_socket, wayland_socket) = if config.graphics.virtio_gpu {
+        let socket = "virtio-gpu.sock";
+
+        let wayland_display = env
+            .wayland_display
+            .clone()
+            .ok_or(VmError::WaylandSocketEnvUnavailable(env::VarError::NotPresent))?;
+
+        let wayland_socket_path = env.runtime_dir.join(wayland_display);
+        let wayland_socket = wayland_socket_path.as_os_str().to_string_lossy();
+
+        let device_params_json = json!({
+            "context-types": "virgl:virgl2:cross-domain",
+            "displays": [{ "hidden":true }],
+            "egl": true,
+            "vulkan": true,
+        });
+        let device_params = serde_json::to_string(&device_params_json).expect("this is valid json");
+
+        let cmd = vec![
+            program(&crosvm),
+            format!("device"),
+            format!("gpu"),
+            format!("--socket={}", socket),
+            format!("--wayland-sock={}", wayland_socket),
+            format!("--params={}", device_params),
+        ];
+
+        support.push(SupportProcess {
+            name: "gpu".to_string(),
+            command: cmd,
+            socket: socket.into(),
+            policy: config.support.policy_for("gpu"),
+        });
+
+        (Some(socket.into()), Some(wayland_socket_path))
+    } else {
+        (None, None)
+    };
+
+    let network = if config.network.assign_tap_device {
+        Some(NetworkRequest {
+            user: env
+                .user
+                .clone()
+                .ok_or(VmError::UserEnvUnavailable(env::VarError::NotPresent))?,
+            queues: config.cpu.cores,
+            name: None,
+        })
+    } else {
+        None
+    };
+
+    let (mut console, mut serial) = config.console.devices();
+    for device in [&mut console, &mut serial] {
+        device.file = device.file.as_ref().map(|file| env.current_dir.join(file));
+    }
+    let mut cmdline = config.cmdline.clone();
+    if config.console.cmdline
+        && !cmdline
+            .split_whitespace()
+            .any(|arg| arg.starts_with("console="))
+    {
+        for name in kernel_consoles(&console, &serial) {
+            if !cmdline.is_empty() {
+                cmdline.push(' ');
+            }
+            cmdline.push_str(&format!("console={}", name));
+        }
+    }
+
+    let mut log_dirs = vec![runtime_log_dir(&dir)];
+    let mut boots = None;
+    if let (true, Some(name)) = (config.logs.persistent, config.name.as_ref()) {
+        log_dirs.push(persistent_log_dir(&env.data_dir, name));
+        boots = Some(boots_dir(&env.data_dir, name));
+    }
+
+    Ok(VmPlan {
+        id: id.to_string(),
+        name: config.name.clone(),
+        dir,
+        kernel: env.current_dir.join(&config.kernel_path),
+        initrd: env.current_dir.join(&config.initrd_path),
+        cmdline,
+        cpus: config.cpu.cores,
+        memory: config
+            .memory
+            .size
+            .checked_mul(1024 * 1024)
+            .ok_or(VmError::MemoryTooLarge(config.memory.size))?,
+        console,
+        serial,
+        detached: false,
+        hypervisor,
+        api_socket: API_SOCKET.into(),
+        boot: config.hypervisor.boot.clone(),
+        restore: None,
+        config_path: None,
+        grace_period: Duration::from_secs(config.shutdown.grace_period),
+        binaries: planned_binaries,
+        support,
+        support_config: config.support.clone(),
+        virtiofsd,
+        socket_timeout: Duration::from_secs(config.support.socket_timeout),
+        max_restarts: config.support.max_restarts,
+        logs: LogPlan {
+            dirs: log_dirs,
+            max_size: config.logs.max_size * 1024,
+            rotations: config.logs.rotations,
+            boots,
+            keep_boots: config.logs.boots,
+        },
+        shares,
+        disks,
+        gpu_socket,
+        wayland_socket,
+        network,
+    })
+}
+
+impl VmPlan {
+    pub(crate) fn share_support(&self, share: &filesystem::Share) -> SupportProcess {
+        share_support(
+            &self.virtiofsd.path,
+            &self.support_config,
+            &share.tag,
+            &share.source,
+            share,
+        )
+    }
+
+    pub fn vm_config(&self, tap_device: Option<&str>) -> VmConfig {
+        VmConfig {
+            payload: PayloadConfig {
+                kernel: self.kernel.clone(),
+                initramfs: self.initrd.clone(),
+                cmdline: self.cmdline.clone(),
+            },
+            cpus: CpusConfig {
+                boot_vcpus: self.cpus,
+                max_vcpus: self.cpus,
+            },
+            memory: MemoryConfig {
+                size: self.memory,
+                mergeable: true,
+                shared: true,
+            },
+            disks: self
+                .disks
+                .iter()
+                .map(|disk| DiskConfig {
+                    path: disk.path.clone(),
+                    readonly: disk.readonly,
+                    serial: disk.serial.clone(),
+                    id: None,
+                })
+                .collect(),
+            fs: self
+                .shares
+                .iter()
+                .map(|share| FsConfig {
+                    tag: share.tag.clone(),
+                    socket: self.dir.join(&share.socket),
+                    num_queues: 1,
+                    queue_size: 1024,
+                    id: None,
+                })
+                .collect(),
+            net: match (self.network.as_ref(), tap_device) {
+                (Some(network), Some(tap_device)) => vec![NetConfig {
+                    tap: tap_device.to_string(),
+                    num_queues: network.queues,
+                    id: None,
+                }],
+                _ => vec![],
+            },
+            gpu: self
+                .gpu_socket
+                .iter()
+                .map(|socket| GpuConfig {
+                    socket: self.dir.join(socket),
+                })
+                .collect(),
+            console: self.console_config(&self.console),
+            serial: self.console_config(&self.serial),
+            watchdog: true,
+        }
+    }
+
+    pub fn captures(&self, device: &console::Device) -> bool {
+        match device.sink {
+            console::Sink::Log | console::Sink::Socket => true,
+            console::Sink::Tty => self.detached,
+            console::Sink::Off | console::Sink::File | console::Sink::Pty => false,
+        }
+    }
+
+    pub fn captures_console(&self) -> bool {
+        self.captures(&self.console) || self.captures(&self.serial)
+    }
+
+    pub fn opens_ptys(&self) -> bool {
+        self.captures_console()
+            || [&self.console, &self.serial]
+                .iter()
+                .any(|device| device.sink == console::Sink::Pty)
+    }
+
+    fn console_config(&self, device: &console::Device) -> ConsoleConfig {
+        match device.sink {
+            console::Sink::Off => ConsoleConfig::mode(ConsoleOutputMode::Null),
+            console::Sink::Tty if !self.detached => ConsoleConfig::mode(ConsoleOutputMode::Tty),
+            console::Sink::File => ConsoleConfig {
+                mode: ConsoleOutputMode::File,
+                file: device.file.clone(),
+                socket: None,
+            },
+            _ => ConsoleConfig::mode(ConsoleOutputMode::Pty),
+        }
+    }
+
+    pub fn stdio(&self) -> StdioMode {
+        let tty = [&self.console, &self.serial]
+            .iter()
+            .any(|device| device.sink == console::Sink::Tty);
+        if tty && !self.detached {
+            StdioMode::Inherit
+        } else {
+            StdioMode::Null
+        }
+    }
+
+    pub fn vm_command(&self, tap_device: Option<&str>) -> Vec<String> {
+        let mut vm_cmd = vec![
+            program(&self.hypervisor),
+            format!("--seccomp=true"),
+            format!("--api-socket"),
+            format!("path={}", self.api_socket.to_string_lossy()),
+        ];
+        if self.boot == hypervisor::Boot::Argv {
+            match self.restore.as_ref() {
+                Some(restore) => {
+                    vm_cmd.push("--restore".to_string());
+                    vm_cmd.push(format!("source_url={}", restore));
+                }
+                None => vm_cmd.extend(self.vm_config(tap_device).to_args()),
+            }
+        }
+        vm_cmd
+    }
+}
+
+impl Display for VmPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(config_path) = self.config_path.as_ref() {
+            writeln!(f, "config: {}", config_path.display())?;
+        }
+        writeln!(f, "working directory: {}", self.dir.display())?;
+        match self.network.as_ref() {
+            Some(network) => match network.name.as_ref() {
+                Some(name) => writeln!(
+                    f,
+                    "tap device: {} requested from containd for user {}",
+                    name, network.user
+                )?,
+                None => writeln!(
+                    f,
+                    "tap device: requested from containd for user {}",
+                    network.user
+                )?,
+            },
+            None => writeln!(f, "tap device: none")?,
+        }
+        for disk in self.disks.iter() {
+            if let Some(create) = disk.create.as_ref() {
+                writeln!(
+                    f,
+                    "disk: {} (created as {} with {}M if missing)",
+                    disk.path.display(),
+                    create.format,
+                    create.size
+                )?;
+            } else {
+                writeln!(f, "disk: {}", disk.path.display())?;
+            }
+        }
+        for binary in self.binaries.iter() {
+            match binary.minimum {
+                Some(minimum) => writeln!(
+                    f,
+                    "binary {}: {} (at least version {})",
+                    binary.name,
+                    binary.path.display(),
+                    minimum
+                )?,
+                None => writeln!(f, "binary {}: {}", binary.name, binary.path.display())?,
+            }
+        }
+        for support in self.support.iter() {
+            writeln!(
+                f,
+                "support process {} (socket {}, on exit {}): {}",
+                support.name,
+                support.socket.display(),
+                support.policy,
+                shell_join(&support.command)
+            )?;
+        }
+        if !self.support.is_empty() {
+            writeln!(
+                f,
+                "support sockets: waiting up to {}s",
+                self.socket_timeout.as_secs()
+            )?;
+            if self.support.iter().any(|s| s.policy == support::Policy::Restart) {
+                writeln!(f, "support restarts: at most {}", self.max_restarts)?;
+            }
+            for dir in self.logs.dirs.iter() {
+                writeln!(f, "support logs: {}", dir.display())?;
+            }
+        }
+        let tap_device = self
+            .network
+            .as_ref()
+            .map(|_| "<tap device from containd>");
+        writeln!(f, "vm: {}", shell_join(&self.vm_command(tap_device)))?;
+        if self.captures_console() {
+            let dir = match self.logs.boots.as_ref() {
+                Some(boots) => format!(
+                    "{}/<boot> (keeping {} boots)",
+                    boots.display(),
+                    self.logs.keep_boots
+                ),
+                None => runtime_log_dir(&self.dir).display().to_string(),
+            };
+            writeln!(f, "console logs: {}", dir)?;
+        }
+        for (name, device, socket) in [
+            ("console", &self.console, CONSOLE_SOCKET),
+            ("serial", &self.serial, SERIAL_SOCKET),
+        ] {
+            if device.sink == console::Sink::Socket {
+                writeln!(
+                    f,
+                    "{}: pty bridged to {}",
+                    name,
+                    self.dir.join(socket).display()
+                )?;
+            }
+        }
+        if let Some(restore) = self.restore.as_ref() {
+            writeln!(f, "restore: {}", restore)?;
+        } else if self.boot == hypervisor::Boot::Api {
+            writeln!(
+                f,
+                "vm config (vm.create): {}",
+                serde_json::to_string(&self.vm_config(tap_device)).expect("vm config is valid json")
+            )?;
+        }
+        writeln!(
+            f,
+            "shutdown: power button, vm.shutdown after {}s, then kill",
+            self.grace_period.as_secs()
+        )
+    }
+}
+
+pub(crate) fn share_socket(tag: &str) -> PathBuf {
+    format!("virtio-fs-{}.sock", tag).into()
+}
+
+pub(crate) fn share_helper(tag: &str) -> String {
+    format!("virtiofs-{}", tag)
+}
+
+fn share_support(
+    virtiofsd: &Path,
+    policies: &support::Support,
+    tag: &str,
+    source: &Path,
+    share: &filesystem::Share,
+) -> SupportProcess {
+    let name = share_helper(tag);
+    let socket = share_socket(tag);
+    SupportProcess {
+        policy: policies.policy_for(&name),
+        command: virtiofsd_command(virtiofsd, &socket, tag, source, share),
+        name,
+        socket,
+    }
+}
+
+fn virtiofsd_command(
+    virtiofsd: &Path,
+    socket: &Path,
+    tag: &str,
+    source: &Path,
+    share: &filesystem::Share,
+) -> Vec<String> {
+    let mut cmd = vec![
+        program(virtiofsd),
+        format!("--socket-path"),
+        format!("{}", socket.to_string_lossy()),
+        format!("--tag"),
+        format!("{}", tag),
+        format!("--shared-dir"),
+        format!("{}", source.to_string_lossy()),
+        format!("--inode-file-handles={}", share.inode_file_handles),
+    ];
+
+    if !share.write {
+        cmd.push("--readonly".to_string());
+    }
+
+    cmd
+}
+
+fn kernel_consoles(console: &console::Device, serial: &console::Device) -> Vec<&'static str> {
+    let interactive = |device: &console::Device| {
+        matches!(
+            device.sink,
+            console::Sink::Tty | console::Sink::Socket | console::Sink::Pty
+        )
+    };
+    let mut consoles = vec![("ttyS0", serial), ("hvc0", console)];
+    if interactive(serial) && !interactive(console) {
+        consoles.reverse();
+    }
+    consoles
+        .into_iter()
+        .filter(|(_, device)| device.sink != console::Sink::Off)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+fn program(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+pub(crate) fn shell_join(cmd: &[String]) -> String {
+    cmd.iter()
+        .map(|arg| {
+            if !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_=./,:+@%".contains(c))
+            {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env() -> Environment {
+        Environment {
+            runtime_dir: "/run/user/1000".into(),
+            data_dir: "/home/user/.local/share".into(),
+            current_dir: "/home/user/vm".into(),
+            user: Some("user".to_string()),
+            wayland_display: Some("wayland-1".to_string()),
+            binaries: Binaries::default(),
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            kernel_path: "kernel".into(),
+            initrd_path: "initrd".into(),
+            cmdline: "quiet".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn plan(config: &Config) -> VmPlan {
+        plan_vm(config, &env(), "abc").unwrap()
+    }
+
+    fn argv(config: &Config) -> Vec<String> {
+        let mut config = config.clone();
+        config.hypervisor.boot = hypervisor::Boot::Argv;
+        plan(&config).vm_command(Some("tap0"))
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn api_boot_only_passes_the_api_socket() {
+        let plan = plan(&config());
+        assert_eq!(plan.dir, PathBuf::from("/run/user/1000/contain/abc"));
+        assert_eq!(
+            plan.vm_command(None),
+            strings(&[
+                "cloud-hypervisor",
+                "--seccomp=true",
+                "--api-socket",
+                "path=cloud-hypervisor.sock",
+            ])
+        );
+        assert!(plan.support.is_empty());
+        assert!(plan.network.is_none());
+    }
+
+    #[test]
+    fn argv_boot_passes_the_vm_config() {
+        let mut config = config();
+        config.cpu.cores = 4;
+        config.memory.size = 2048;
+        let args = argv(&config);
+        assert_eq!(
+            args[4..],
+            strings(&[
+                "--kernel",
+                "/home/user/vm/kernel",
+                "--initramfs",
+                "/home/user/vm/initrd",
+                "--cmdline",
+                "quiet",
+                "--memory=mergeable=on,shared=on,size=2048M",
+                "--cpus",
+                "boot=4",
+                "--watchdog",
+                "--console",
+                "null",
+                "--serial",
+                "null",
+            ])
+        );
+    }
+
+    #[test]
+    fn restore_replaces_the_vm_config() {
+        let mut config = config();
+        config.hypervisor.boot = hypervisor::Boot::Argv;
+        let mut plan = plan(&config);
+        plan.restore = Some("file:///snapshots/a".to_string());
+        assert_eq!(
+            plan.vm_command(None)[4..],
+            strings(&["--restore", "source_url=file:///snapshots/a"])
+        );
+    }
+
+    #[test]
+    fn binaries_are_resolved_against_the_current_dir() {
+        let mut config = config();
+        config.binaries.cloud_hypervisor = Some("bin/cloud-hypervisor-graphics".into());
+        config.binaries.virtiofsd = Some("virtiofsd-custom".into());
+        let mut env = env();
+        env.binaries.virtiofsd = Some("/usr/bin/virtiofsd".into());
+        env.binaries.crosvm = Some("/usr/bin/crosvm".into());
+        let binaries = config.binaries.or(&env.binaries);
+        assert_eq!(binaries.virtiofsd(), PathBuf::from("virtiofsd-custom"));
+        assert_eq!(binaries.crosvm(), PathBuf::from("/usr/bin/crosvm"));
+
+        let plan = plan_vm(&config, &env, "abc").unwrap();
+        assert_eq!(
+            plan.hypervisor,
+            PathBuf::from("/home/user/vm/bin/cloud-hypervisor-graphics")
+        );
+    }
+
+    #[test]
+    fn shares_start_virtiofsd_and_add_fs_devices() {
+        let mut config = config();
+        config.filesystem.shares = vec![
+            filesystem::Share {
+                source: "src".into(),
+                tag: "src".to_string(),
+                ..Default::default()
+            },
+            filesystem::Share {
+                source: "/data".into(),
+                tag: "data".to_string(),
+                write: false,
+                inode_file_handles: filesystem::InodeFileHandles::Prefer,
+            },
+        ];
+        let plan = plan(&config);
+
+        assert_eq!(
+            plan.binaries
+                .iter()
+                .map(|b| b.name.as_str())
+                .collect::<Vec<_>>(),
+            ["cloud-hypervisor", "virtiofsd"]
+        );
+        assert_eq!(plan.support.len(), 2);
+        assert_eq!(plan.support[0].name, "virtiofs-src");
+        assert_eq!(plan.support[0].socket, PathBuf::from("virtio-fs-src.sock"));
+        assert_eq!(
+            plan.support[0].command,
+            strings(&[
+                "virtiofsd",
+                "--socket-path",
+                "virtio-fs-src.sock",
+                "--tag",
+                "src",
+                "--shared-dir",
+                "/home/user/vm/src",
+                "--inode-file-handles=never",
+            ])
+        );
+        assert_eq!(
+            plan.support[1].command,
+            strings(&[
+                "virtiofsd",
+                "--socket-path",
+                "virtio-fs-data.sock",
+                "--tag",
+                "data",
+                "--shared-dir",
+                "/data",
+                "--inode-file-handles=prefer",
+                "--readonly",
+            ])
+        );
+
+        let fs = plan.vm_config(None).fs;
+        assert_eq!(
+            fs[1].socket,
+            PathBuf::from("/run/user/1000/contain/abc/virtio-fs-data.sock")
+        );
+        assert_eq!(fs[1].tag, "data");
+
+        let args = argv(&config);
+        let fs_arg = args.iter().position(|arg| arg == "--fs").unwrap();
+        assert_eq!(
+            args[fs_arg + 1..fs_arg + 3],
+            strings(&[
+                "socket=/run/user/1000/contain/abc/virtio-fs-src.sock,tag=src",
+                "socket=/run/user/1000/contain/abc/virtio-fs-data.sock,tag=data",
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_share_tags_are_rejected() {
+        let mut config = config();
+        config.filesystem.shares = vec![filesystem::Share {
+            source: "src".into(),
+            tag: "a b".to_string(),
+            ..Default::default()
+        }];
+        assert!(matches!(
+            plan_vm(&config, &env(), "abc"),
+            Err(VmError::InvalidShareTag(_))
+        ));
+    }
+
+    #[test]
+    fn disks_resolve_to_the_data_dir_of_named_vms() {
+        let mut config = config();
+        config.name = Some("dev".to_string());
+        config.filesystem.disks = vec![
+            filesystem::Disk {
+                tag: "home".to_string(),
+                size: 512,
+                ..Default::default()
+            },
+            filesystem::Disk {
+                source: Some("images/base.raw".into()),
+                tag: "base".to_string(),
+                write: false,
+                create: false,
+                format: filesystem::Format::Raw,
+                ..Default::default()
+            },
+        ];
+        let plan = plan(&config);
+
+        assert_eq!(
+            plan.disks,
+            vec![
+                PlannedDisk {
+                    serial: "home".to_string(),
+                    path: "/home/user/.local/share/contain/dev/home.qcow2".into(),
+                    readonly: false,
+                    create: Some(DiskCreation {
+                        size: 512,
+                        format: filesystem::Format::Qcow2,
+                    }),
+                },
+                PlannedDisk {
+                    serial: "base".to_string(),
+                    path: "/home/user/vm/images/base.raw".into(),
+                    readonly: true,
+                    create: None,
+                },
+            ]
+        );
+
+        let args = argv(&config);
+        let disk_arg = args.iter().position(|arg| arg == "--disk").unwrap();
+        assert_eq!(
+            args[disk_arg + 1..disk_arg + 3],
+            strings(&[
+                "path=/home/user/.local/share/contain/dev/home.qcow2,serial=home,readonly=off",
+                "path=/home/user/vm/images/base.raw,serial=base,readonly=on",
+            ])
+        );
+    }
+
+    #[test]
+    fn unnamed_vms_need_disk_sources() {
+        let mut config = config();
+        config.filesystem.disks = vec![filesystem::Disk {
+            tag: "home".to_string(),
+            ..Default::default()
+        }];
+        assert!(matches!(
+            plan_vm(&config, &env(), "abc"),
+            Err(VmError::FailedToResolveDiskLocation)
+        ));
+    }
+
+    #[test]
+    fn network_requests_a_tap_device_with_a_queue_per_core() {
+        let mut config = config();
+        config.network.assign_tap_device = true;
+        config.cpu.cores = 2;
+        let plan = plan(&config);
+
+        assert_eq!(
+            plan.network,
+            Some(NetworkRequest {
+                user: "user".to_string(),
+                queues: 2,
+                name: None,
+            })
+        );
+        assert!(plan.vm_config(None).net.is_empty());
+        let net = plan.vm_config(Some("tap0")).net;
+        assert_eq!(net[0].tap, "tap0");
+        assert_eq!(net[0].num_queues, 2);
+
+        let args = argv(&config);
+        let net_arg = args.iter().position(|arg| arg == "--net").unwrap();
+        assert_eq!(args[net_arg + 1], "num_queues=2,tap=tap0");
+
+        let mut env = env();
+        env.user = None;
+        assert!(matches!(
+            plan_vm(&config, &env, "abc"),
+            Err(VmError::UserEnvUnavailable(_))
+        ));
+    }
+
+    #[test]
+    fn gpu_starts_crosvm_with_the_wayland_socket() {
+        let mut config = config();
+        config.graphics.virtio_gpu = true;
+        let plan = plan(&config);
+
+        assert_eq!(plan.gpu_socket, Some("virtio-gpu.sock".into()));
+        assert_eq!(plan.wayland_socket, Some("/run/user/1000/wayland-1".into()));
+        assert_eq!(plan.support[0].name, "gpu");
+        assert_eq!(
+            plan.support[0].command[..5],
+            strings(&[
+                "crosvm",
+                "device",
+                "gpu",
+                "--socket=virtio-gpu.sock",
+                "--wayland-sock=/run/user/1000/wayland-1",
+            ])
+        );
+        assert!(plan.support[0].command[5].starts_with("--params={"));
+        assert_eq!(
+            plan.vm_config(None).gpu[0].socket,
+            PathBuf::from("/run/user/1000/contain/abc/virtio-gpu.sock")
+        );
+
+        let args = argv(&config);
+        let gpu_arg = args.iter().position(|arg| arg == "--gpu").unwrap();
+        assert_eq!(
+            args[gpu_arg + 1],
+            "socket=/run/user/1000/contain/abc/virtio-gpu.sock"
+        );
+
+        let mut env = env();
+        env.wayland_display = None;
+        assert!(matches!(
+            plan_vm(&config, &env, "abc"),
+            Err(VmError::WaylandSocketEnvUnavailable(_))
+        ));
+    }
+
+    fn consoles(config: &Config, detached: bool) -> (ConsoleConfig, ConsoleConfig, StdioMode) {
+        let mut plan = plan(config);
+        plan.detached = detached;
+        let vm_config = plan.vm_config(None);
+        (vm_config.console, vm_config.serial, plan.stdio())
+    }
+
+    fn sinks(console: console::Sink, serial: console::Sink) -> Config {
+        let mut config = config();
+        config.console.console = console::Device::sink(console);
+        config.console.serial = console::Device::sink(serial);
+        config
+    }
+
+    #[test]
+    fn console_sinks_map_to_hypervisor_modes() {
+        let null = ConsoleConfig::mode(ConsoleOutputMode::Null);
+        let tty = ConsoleConfig::mode(ConsoleOutputMode::Tty);
+        let pty = ConsoleConfig::mode(ConsoleOutputMode::Pty);
+
+        let off = sinks(console::Sink::Off, console::Sink::Off);
+        assert_eq!(
+            consoles(&off, false),
+            (null.clone(), null.clone(), StdioMode::Null)
+        );
+
+        let tty_log = sinks(console::Sink::Tty, console::Sink::Log);
+        assert_eq!(
+            consoles(&tty_log, false),
+            (tty.clone(), pty.clone(), StdioMode::Inherit)
+        );
+        assert_eq!(
+            consoles(&tty_log, true),
+            (pty.clone(), pty.clone(), StdioMode::Null)
+        );
+
+        let socket_pty = sinks(console::Sink::Socket, console::Sink::Pty);
+        assert_eq!(
+            consoles(&socket_pty, false),
+            (pty.clone(), pty.clone(), StdioMode::Null)
+        );
+        let plan = plan(&socket_pty);
+        assert!(plan.captures(&plan.console));
+        assert!(!plan.captures(&plan.serial));
+        assert!(plan.opens_ptys());
+
+        let mut file = sinks(console::Sink::Off, console::Sink::File);
+        file.console.serial.file = Some("serial.log".into());
+        let (console, serial, _) = consoles(&file, false);
+        assert_eq!(console, null);
+        assert_eq!(serial.mode, ConsoleOutputMode::File);
+        assert_eq!(serial.file, Some("/home/user/vm/serial.log".into()));
+        assert!(!plan_vm(&file, &env(), "abc").unwrap().opens_ptys());
+
+        let args = argv(&file);
+        let serial_arg = args.iter().position(|arg| arg == "--serial").unwrap();
+        assert_eq!(args[serial_arg + 1], "file=/home/user/vm/serial.log");
+    }
+
+    #[test]
+    fn legacy_console_modes_use_a_single_device() {
+        let null = ConsoleConfig::mode(ConsoleOutputMode::Null);
+        let tty = ConsoleConfig::mode(ConsoleOutputMode::Tty);
+        let pty = ConsoleConfig::mode(ConsoleOutputMode::Pty);
+        let mode = |mode| {
+            let mut config = config();
+            config.console.mode = Some(mode);
+            config
+        };
+
+        let off = mode(console::Mode::Off);
+        assert_eq!(
+            consoles(&off, false),
+            (null.clone(), null.clone(), StdioMode::Null)
+        );
+
+        let log = mode(console::Mode::Log);
+        assert_eq!(
+            consoles(&log, false),
+            (pty.clone(), null.clone(), StdioMode::Null)
+        );
+
+        let on = mode(console::Mode::On);
+        assert_eq!(
+            consoles(&on, false),
+            (tty.clone(), null.clone(), StdioMode::Inherit)
+        );
+        assert_eq!(
+            consoles(&on, true),
+            (pty.clone(), null.clone(), StdioMode::Null)
+        );
+
+        let serial = mode(console::Mode::Serial);
+        assert_eq!(
+            consoles(&serial, false),
+            (null.clone(), tty.clone(), StdioMode::Inherit)
+        );
+        assert_eq!(
+            consoles(&serial, true),
+            (null.clone(), pty.clone(), StdioMode::Null)
+        );
+
+        let socket = mode(console::Mode::Socket);
+        assert_eq!(
+            consoles(&socket, false),
+            (pty.clone(), null.clone(), StdioMode::Null)
+        );
+    }
+
+    #[test]
+    fn console_cmdline_follows_the_interactive_device() {
+        let cmdline = |console, serial| {
+            let mut config = sinks(console, serial);
+            config.console.cmdline = true;
+            plan(&config).cmdline
+        };
+        assert_eq!(cmdline(console::Sink::Off, console::Sink::Off), "quiet");
+        assert_eq!(
+            cmdline(console::Sink::Tty, console::Sink::Off),
+            "quiet console=hvc0"
+        );
+        assert_eq!(
+            cmdline(console::Sink::Log, console::Sink::Log),
+            "quiet console=ttyS0 console=hvc0"
+        );
+        assert_eq!(
+            cmdline(console::Sink::Log, console::Sink::Tty),
+            "quiet console=hvc0 console=ttyS0"
+        );
+
+        let mut config = sinks(console::Sink::Tty, console::Sink::Off);
+        config.console.cmdline = true;
+        config.cmdline = "console=tty0".to_string();
+        assert_eq!(plan(&config).cmdline, "console=tty0");
+        config.console.cmdline = false;
+        config.cmdline = String::new();
+        assert_eq!(plan(&config).cmdline, "");
+    }
+
+    #[test]
+    fn oversized_memory_is_rejected() {
+        let mut config = config();
+        config.memory.size = u64::MAX / 1024;
+        assert!(matches!(
+            plan_vm(&config, &env(), "abc"),
+            Err(VmError::MemoryTooLarge(size)) if size == u64::MAX / 1024
+        ));
+    }
+}
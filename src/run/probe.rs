@@ -0,0 +1,135 @@
+use regex::Regex;
+use std::fmt::Display;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::task::spawn_blocking;
+use tokio::time::timeout;
+
+use crate::run::launcher::{Invocation, Launcher, StdioMode};
+use crate::run::plan::PlannedBinary;
+use crate::run::VmError;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Version(pub u64, pub u64, pub u64);
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+static PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+static VERSION_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"([0-9]+)\.([0-9]+)(?:\.([0-9]+))?").unwrap());
+
+impl Version {
+    pub fn find(output: &str) -> Option<Self> {
+        let captures = VERSION_REGEX.captures(output)?;
+        let part = |i: usize| {
+            captures
+                .get(i)
+                .map(|m| m.as_str().parse().ok())
+                .unwrap_or(Some(0))
+        };
+        Some(Self(part(1)?, part(2)?, part(3)?))
+    }
+}
+
+pub async fn probe(launcher: &dyn Launcher, binary: &PlannedBinary) -> Result<Version, VmError> {
+    probe_within(launcher, binary, PROBE_TIMEOUT).await
+}
+
+async fn probe_within(
+    launcher: &dyn Launcher,
+    binary: &PlannedBinary,
+    limit: Duration,
+) -> Result<Version, VmError> {
+    let missing = |source| VmError::BinaryMissing {
+        name: binary.name.clone(),
+        path: binary.path.clone(),
+        source,
+    };
+
+    let process = launcher
+        .launch(&Invocation {
+            command: vec![
+                binary.path.to_string_lossy().to_string(),
+                "--version".to_string(),
+            ],
+            dir: PathBuf::from("/"),
+            stdio: StdioMode::PipedStdout,
+            sockets: vec![],
+        })
+        .map_err(missing)?;
+
+    let probing = process.clone();
+    let probed = timeout(
+        limit,
+        spawn_blocking(move || {
+            let mut output = String::new();
+            if let Some(mut stdout) = probing.take_stdout() {
+                stdout.read_to_string(&mut output)?;
+            }
+            probing.wait().map(|status| (output, status))
+        }),
+    )
+    .await;
+    let (output, status) = match probed {
+        Ok(joined) => joined
+            .expect("probing a binary should not panic")
+            .map_err(missing)?,
+        Err(_) => {
+            _ = process.kill();
+            return Err(VmError::BinaryProbeTimeout {
+                name: binary.name.clone(),
+                path: binary.path.clone(),
+                timeout: limit,
+            });
+        }
+    };
+
+    let version = Version::find(&output)
+        .filter(|_| status.success())
+        .ok_or_else(|| VmError::BinaryVersionUnknown {
+            name: binary.name.clone(),
+            path: binary.path.clone(),
+            output: output.trim().to_string(),
+        })?;
+
+    if let Some(minimum) = binary.minimum {
+        if version < minimum {
+            return Err(VmError::BinaryTooOld {
+                name: binary.name.clone(),
+                path: binary.path.clone(),
+                found: version,
+                minimum,
+            });
+        }
+    }
+
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run::launcher::fake::RecordingLauncher;
+
+    #[tokio::test]
+    async fn hanging_version_probes_time_out() {
+        let launcher = RecordingLauncher::new();
+        launcher.set_version_hangs("virtiofsd");
+        let binary = PlannedBinary {
+            name: "virtiofsd".to_string(),
+            path: "virtiofsd".into(),
+            minimum: None,
+        };
+
+        let result = probe_within(&launcher, &binary, Duration::from_millis(100)).await;
+
+        assert!(matches!(result, Err(VmError::BinaryProbeTimeout { .. })));
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::select;
+use tokio::sync::watch;
+use tokio::task::spawn_blocking;
+use tokio::time::sleep;
+
+use crate::config::support::Policy;
+use crate::instance::{HelperInfo, InstanceError, InstanceInfo};
+use crate::logs::{log_path, RotatingFile};
+use crate::run::launcher::{Invocation, Launcher, Process, StdioMode};
+use crate::run::plan::{LogPlan, SupportProcess, VmPlan};
+use crate::run::VmError;
+
+static STDERR_TAIL_LINES: usize = 20;
+
+pub(crate) struct SupportHandle {
+    pub(crate) name: String,
+    pub(crate) socket: PathBuf,
+    pub(crate) process: Arc<dyn Process>,
+    support: SupportProcess,
+    restarts: u64,
+    ignored: bool,
+    stderr: StderrTail,
+}
+
+impl SupportHandle {
+    pub(crate) fn start(
+        launcher: &dyn Launcher,
+        support: &SupportProcess,
+        dir: &Path,
+        logs: &LogPlan,
+    ) -> Result<Self, VmError> {
+        let mut files = vec![];
+        for log_dir in logs.dirs.iter() {
+            let path = log_path(log_dir, &support.name);
+            files.push(
+                RotatingFile::open(path.clone(), logs.max_size, logs.rotations)
+                    .map_err(|e| VmError::FailedToOpenLogFile(path, e))?,
+            );
+        }
+        let files = Arc::new(Mutex::new(files));
+
+        let process = launcher
+            .launch(&Invocation {
+                command: support.command.clone(),
+                dir: dir.to_path_buf(),
+                stdio: StdioMode::PipedOutput,
+                sockets: vec![support.socket.clone()],
+            })
+            .map_err(VmError::FailedToSpawnSupportProcess)?;
+
+        if let Some(output) = process.take_stdout() {
+            let files = files.clone();
+            thread::spawn(move || {
+                copy_lines(output, |line| write_log(&files, line));
+            });
+        }
+
+        let lines = Arc::new(Mutex::new(VecDeque::new()));
+        let (done_tx, done_rx) = mpsc::channel();
+        if let Some(output) = process.take_stderr() {
+            let stderr = lines.clone();
+            thread::spawn(move || {
+                copy_lines(output, |line| {
+                    write_log(&files, line);
+                    let mut stderr = stderr.lock().unwrap();
+                    if stderr.len() == STDERR_TAIL_LINES {
+                        stderr.pop_front();
+                    }
+                    stderr.push_back(String::from_utf8_lossy(line).trim_end().to_string());
+                });
+                _ = done_tx.send(());
+            });
+        }
+
+        Ok(Self {
+            name: support.name.clone(),
+            socket: support.socket.clone(),
+            process,
+            support: support.clone(),
+            restarts: 0,
+            ignored: false,
+            stderr: StderrTail {
+                lines,
+                done: Arc::new(Mutex::new(done_rx)),
+            },
+        })
+    }
+}
+
+#[derive(Clone)]
+struct StderrTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    done: Arc<Mutex<mpsc::Receiver<()>>>,
+}
+
+impl StderrTail {
+    // Blocks for a moment so the last lines of an exited process are read.
+    fn read(&self) -> String {
+        _ = self
+            .done
+            .lock()
+            .unwrap()
+            .recv_timeout(Duration::from_millis(500));
+        let lines = self.lines.lock().unwrap();
+        lines.iter().cloned().collect::<Vec<_>>().join("\n")
+    }
+}
+
+fn copy_lines(output: Box<dyn Read + Send>, mut f: impl FnMut(&[u8])) {
+    let mut reader = io::BufReader::new(output);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => f(&line),
+        }
+    }
+}
+
+fn write_log(files: &Mutex<Vec<RotatingFile>>, line: &[u8]) {
+    for file in files.lock().unwrap().iter_mut() {
+        _ = file.write_line(line);
+    }
+}
+
+pub(crate) enum Readiness {
+    Ready,
+    ShutdownRequested,
+}
+
+pub(crate) async fn wait_for_sockets(
+    handles: &Mutex<Vec<SupportHandle>>,
+    dir: &Path,
+    timeout: Duration,
+    shutdown_rx: &mut watch::Receiver<bool>,
+) -> Result<Readiness, VmError> {
+    let started = Instant::now();
+    loop {
+        if *shutdown_rx.borrow() {
+            return Ok(Readiness::ShutdownRequested);
+        }
+
+        let mut missing = vec![];
+        let mut exited = None;
+        for handle in handles.lock().unwrap().iter() {
+            if let Some(status) = handle
+                .process
+                .try_wait()
+                .map_err(VmError::FailedToWaitOnSupportProcess)?
+            {
+                exited = Some((handle.name.clone(), status, handle.stderr.clone()));
+                break;
+            }
+            if !dir
+                .join(&handle.socket)
+                .try_exists()
+                .map_err(VmError::FailedToCheckForSupportSocket)?
+            {
+                missing.push(handle.name.clone());
+            }
+        }
+
+        if let Some((name, status, stderr)) = exited {
+            let stderr = spawn_blocking(move || stderr.read())
+                .await
+                .unwrap_or_default();
+            return Err(VmError::SupportProcessExited {
+                name,
+                status,
+                stderr,
+            });
+        }
+        if missing.is_empty() {
+            return Ok(Readiness::Ready);
+        }
+        if started.elapsed() >= timeout {
+            return Err(VmError::SupportSocketTimeout { timeout, missing });
+        }
+
+        select! {
+            _ = sleep(Duration::from_millis(100)) => {},
+            _ = shutdown_rx.changed() => {},
+        };
+    }
+}
+
+pub(crate) struct InstanceRecord {
+    dir: PathBuf,
+    info: Mutex<InstanceInfo>,
+}
+
+impl InstanceRecord {
+    pub(crate) fn create(dir: &Path, info: InstanceInfo) -> Result<Self, InstanceError> {
+        info.write(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            info: Mutex::new(info),
+        })
+    }
+
+    pub(crate) fn update(&self, f: impl FnOnce(&mut InstanceInfo)) -> Result<(), InstanceError> {
+        let mut info = self.info.lock().unwrap();
+        f(&mut info);
+        info.write(&self.dir)
+    }
+
+    pub(crate) fn update_helpers(&self, f: impl FnOnce(&mut Vec<HelperInfo>)) {
+        if let Err(e) = self.update(|info| f(&mut info.helpers)) {
+            eprintln!("failed to record support processes: {}", e);
+        }
+    }
+}
+
+fn record_restart(record: &InstanceRecord, name: &str, pid: u32) {
+    record.update_helpers(|helpers| {
+        for helper in helpers.iter_mut().filter(|h| h.name == name) {
+            helper.pid = pid;
+        }
+    });
+}
+
+pub(crate) async fn supervise(
+    handles: Arc<Mutex<Vec<SupportHandle>>>,
+    record: Arc<InstanceRecord>,
+    plan: VmPlan,
+    launcher: Arc<dyn Launcher>,
+    shutdown_tx: watch::Sender<bool>,
+) {
+    loop {
+        sleep(Duration::from_millis(100)).await;
+
+        let mut exited = vec![];
+        for handle in handles.lock().unwrap().iter_mut() {
+            if handle.ignored {
+                continue;
+            }
+            let Ok(Some(status)) = handle.process.try_wait() else {
+                continue;
+            };
+
+            let support = &handle.support;
+            match support.policy {
+                Policy::Ignore => {
+                    eprintln!(
+                        "support process {} exited with {}, ignoring",
+                        support.name, status
+                    );
+                    handle.ignored = true;
+                }
+                Policy::Restart if handle.restarts < plan.max_restarts => {
+                    exited.push((
+                        handle.process.clone(),
+                        support.clone(),
+                        status,
+                        handle.restarts + 1,
+                    ));
+                }
+                Policy::Restart => {
+                    eprintln!(
+                        "support process {} exited with {} after {} restarts, shutting down vm",
+                        support.name, status, handle.restarts
+                    );
+                    _ = shutdown_tx.send(true);
+                    return;
+                }
+                Policy::Shutdown => {
+                    eprintln!(
+                        "support process {} exited with {}, shutting down vm",
+                        support.name, status
+                    );
+                    _ = shutdown_tx.send(true);
+                    return;
+                }
+            }
+        }
+
+        for (process, support, status, restarts) in exited {
+            eprintln!(
+                "support process {} exited with {}, restarting ({}/{})",
+                support.name, status, restarts, plan.max_restarts
+            );
+            _ = fs::remove_file(plan.dir.join(&support.socket));
+            let mut restarted =
+                match SupportHandle::start(launcher.as_ref(), &support, &plan.dir, &plan.logs) {
+                    Ok(restarted) => restarted,
+                    Err(e) => {
+                        eprintln!(
+                            "failed to restart support process {}: {}, shutting down vm",
+                            support.name, e
+                        );
+                        _ = shutdown_tx.send(true);
+                        return;
+                    }
+                };
+            restarted.restarts = restarts;
+
+            let mut handles = handles.lock().unwrap();
+            match handles
+                .iter_mut()
+                .find(|handle| Arc::ptr_eq(&handle.process, &process))
+            {
+                Some(handle) => {
+                    record_restart(&record, &support.name, restarted.process.id());
+                    *handle = restarted;
+                }
+                // Stopped through the runner api while it was restarting.
+                None => {
+                    _ = restarted.process.kill();
+                    _ = restarted.process.wait();
+                }
+            }
+        }
+    }
+}
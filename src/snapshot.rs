@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+use thiserror::Error;
+
+use crate::client::RequestError;
+use crate::config::validate::{CheckIsValidIdentifier, IdentifierValidationError};
+use crate::control::api_client;
+use crate::hypervisor::{ApiClient, VmState};
+use crate::instance::{self, InstanceError};
+
+pub static SNAPSHOT_INFO_FILE: &str = "contain-snapshot.json";
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("data dir unavailable")]
+    DataDirUnavailable,
+    #[error("unable to find vm")]
+    Instance(#[from] InstanceError),
+    #[error("vm {0} has no name, only named vms keep snapshots")]
+    Unnamed(String),
+    #[error("the vm has no name, pass the path of a snapshot dir instead")]
+    NoVmName,
+    #[error("invalid snapshot name")]
+    InvalidName(IdentifierValidationError),
+    #[error("unable to talk to the hypervisor of vm {id}")]
+    Request {
+        id: String,
+        #[source]
+        source: RequestError,
+    },
+    #[error("cannot snapshot vm {id} because it is {state}")]
+    InvalidState { id: String, state: VmState },
+    #[error("no snapshot found at {0:?}")]
+    NotFound(PathBuf),
+    #[error("unable to access snapshot {0:?}")]
+    Io(PathBuf, #[source] io::Error),
+    #[error("invalid snapshot info {0:?}")]
+    Invalid(PathBuf, #[source] serde_json::Error),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct SnapshotInfo {
+    pub vm_id: String,
+    pub vm_name: String,
+    pub tap_device: Option<String>,
+    pub created: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Snapshot {
+    pub dir: PathBuf,
+    pub info: SnapshotInfo,
+}
+
+impl Snapshot {
+    pub fn url(&self) -> String {
+        format!("file://{}", self.dir.to_string_lossy())
+    }
+
+    pub fn read(dir: &Path) -> Result<Self, SnapshotError> {
+        let path = dir.join(SNAPSHOT_INFO_FILE);
+        if !path.is_file() {
+            return Err(SnapshotError::NotFound(dir.to_path_buf()));
+        }
+        let json = fs::read_to_string(&path).map_err(|e| SnapshotError::Io(path.clone(), e))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            info: serde_json::from_str(&json).map_err(|e| SnapshotError::Invalid(path, e))?,
+        })
+    }
+}
+
+pub fn snapshots_dir(vm_name: &str) -> Result<PathBuf, SnapshotError> {
+    dirs::data_dir()
+        .map(|p| p.join("contain").join(vm_name).join("snapshots"))
+        .ok_or(SnapshotError::DataDirUnavailable)
+}
+
+pub fn uses_tap_device(vm_name: &str, tap_device: &str) -> bool {
+    let Some(entries) = snapshots_dir(vm_name)
+        .ok()
+        .and_then(|dir| fs::read_dir(dir).ok())
+    else {
+        return false;
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| Snapshot::read(&entry.path()).ok())
+        .any(|snapshot| snapshot.info.tap_device.as_deref() == Some(tap_device))
+}
+
+pub fn find(vm_name: Option<&str>, snapshot: &str) -> Result<Snapshot, SnapshotError> {
+    let path = Path::new(snapshot);
+    if path.is_dir() || snapshot.contains(std::path::MAIN_SEPARATOR) {
+        return Snapshot::read(path);
+    }
+    let vm_name = vm_name.ok_or(SnapshotError::NoVmName)?;
+    Snapshot::read(&snapshots_dir(vm_name)?.join(snapshot))
+}
+
+pub async fn snapshot(vm: &str, name: &str) -> Result<Snapshot, SnapshotError> {
+    let name = name
+        .to_string()
+        .check_is_valid_identifier()
+        .map_err(SnapshotError::InvalidName)?;
+    let instance = instance::find(vm)?;
+    let id = instance.info.id.clone();
+    let vm_name = instance
+        .info
+        .name
+        .clone()
+        .ok_or_else(|| SnapshotError::Unnamed(id.clone()))?;
+    let api = api_client(&instance);
+    let request_error = |source| SnapshotError::Request {
+        id: id.clone(),
+        source,
+    };
+
+    let state = api.info().await.map_err(request_error)?.state;
+    match state {
+        VmState::Running => api.pause().await.map_err(request_error)?,
+        VmState::Paused => (),
+        state => return Err(SnapshotError::InvalidState { id, state }),
+    }
+
+    let dir = snapshots_dir(&vm_name)?.join(&name);
+    let snapshot = Snapshot {
+        dir: dir.clone(),
+        info: SnapshotInfo {
+            vm_id: id.clone(),
+            vm_name,
+            tap_device: instance.info.tap_device.clone(),
+            created: instance::unix_time(),
+        },
+    };
+
+    let result = write(&api, &snapshot).await;
+    if state == VmState::Running {
+        if let Err(e) = api.resume().await {
+            if result.is_ok() {
+                return Err(request_error(e));
+            }
+            eprintln!("failed to resume vm {} after the failed snapshot: {}", id, e);
+        }
+    }
+    result?;
+
+    Ok(snapshot)
+}
+
+async fn write(api: &ApiClient, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+    let dir = &snapshot.dir;
+    let temp = sibling(dir, "tmp");
+    let result = write_to(api, snapshot, &temp).await;
+    if result.is_err() {
+        _ = fs::remove_dir_all(&temp);
+        return result;
+    }
+
+    let old = sibling(dir, "old");
+    if dir.exists() {
+        _ = fs::remove_dir_all(&old);
+        fs::rename(dir, &old).map_err(|e| SnapshotError::Io(dir.clone(), e))?;
+    }
+    fs::rename(&temp, dir).map_err(|e| SnapshotError::Io(dir.clone(), e))?;
+    if old.exists() {
+        fs::remove_dir_all(&old).map_err(|e| SnapshotError::Io(old, e))?;
+    }
+    Ok(())
+}
+
+async fn write_to(api: &ApiClient, snapshot: &Snapshot, dir: &Path) -> Result<(), SnapshotError> {
+    if dir.exists() {
+        fs::remove_dir_all(dir).map_err(|e| SnapshotError::Io(dir.to_path_buf(), e))?;
+    }
+    fs::create_dir_all(dir).map_err(|e| SnapshotError::Io(dir.to_path_buf(), e))?;
+
+    api.snapshot(&format!("file://{}", dir.to_string_lossy()))
+        .await
+        .map_err(|source| SnapshotError::Request {
+            id: snapshot.info.vm_id.clone(),
+            source,
+        })?;
+
+    let path = dir.join(SNAPSHOT_INFO_FILE);
+    let json = serde_json::to_string_pretty(&snapshot.info).expect("snapshot info is valid json");
+    fs::write(&path, json).map_err(|e| SnapshotError::Io(path, e))
+}
+
+fn sibling(dir: &Path, suffix: &str) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(dir.file_name().unwrap_or_default());
+    name.push(format!(".{}", suffix));
+    dir.with_file_name(name)
+}
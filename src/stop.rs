@@ -0,0 +1,129 @@
+use std::time::{Duration, Instant};
+use std::{fs, io};
+use thiserror::Error;
+use tokio::time::sleep;
+
+use crate::client::{delete_tap_device, RequestError};
+use crate::hotplug::{DeviceKind, HotplugError, Hotplugged};
+use crate::instance::{self, runs_in, send_signal, Instance, InstanceError, Status};
+use crate::run::describe;
+use crate::snapshot;
+
+#[derive(Error, Debug)]
+pub enum StopError {
+    #[error("unable to find vm")]
+    Instance(#[from] InstanceError),
+    #[error("runner of vm {0} exited without cleaning up, use kill to remove it")]
+    Stale(String),
+    #[error("unable to signal runner of vm {id}")]
+    Signal {
+        id: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("vm {id} did not stop within {}s, use kill to force it", .timeout.as_secs())]
+    Timeout { id: String, timeout: Duration },
+    #[error("unable to read hotplugged devices of vm {id}")]
+    Hotplug {
+        id: String,
+        #[source]
+        source: HotplugError,
+    },
+    #[error("failed to delete tap device {name}")]
+    DeleteTapDevice {
+        name: String,
+        #[source]
+        source: RequestError,
+    },
+    #[error("failed to delete runtime dir of vm {id}")]
+    DeleteRuntimeDir {
+        id: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error(
+        "failed to clean up vm {id}:{}",
+        .errors.iter().map(|e| format!("\n  {}", describe(e))).collect::<String>()
+    )]
+    Cleanup { id: String, errors: Vec<StopError> },
+}
+
+pub async fn stop(vm: &str, timeout: Duration) -> Result<Instance, StopError> {
+    let instance = instance::find(vm)?;
+    let id = instance.info.id.clone();
+    if instance.status() == Status::Stale {
+        return Err(StopError::Stale(id));
+    }
+
+    send_signal(instance.info.pid, libc::SIGTERM)
+        .map_err(|source| StopError::Signal { id: id.clone(), source })?;
+
+    let started = Instant::now();
+    loop {
+        if !instance.dir.exists() {
+            return Ok(instance);
+        }
+        if instance.status() == Status::Stale && send_signal(instance.info.pid, 0).is_err() {
+            return Err(StopError::Stale(id));
+        }
+        if started.elapsed() >= timeout {
+            return Err(StopError::Timeout { id, timeout });
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+pub async fn kill(vm: &str) -> Result<Instance, StopError> {
+    let instance = instance::find(vm)?;
+    let id = instance.info.id.clone();
+    let hotplugged = Hotplugged::read(&instance.dir).map_err(|source| StopError::Hotplug {
+        id: id.clone(),
+        source,
+    })?;
+
+    if instance.status() == Status::Running {
+        _ = send_signal(instance.info.pid, libc::SIGKILL);
+    }
+
+    let mut pids: Vec<u32> = instance.info.helpers.iter().map(|h| h.pid).collect();
+    let mut tap_devices: Vec<(String, bool)> = instance
+        .info
+        .tap_device
+        .iter()
+        .map(|name| {
+            let snapshotted = (instance.info.name.as_deref())
+                .is_some_and(|vm_name| snapshot::uses_tap_device(vm_name, name));
+            (name.clone(), snapshotted)
+        })
+        .collect();
+    for device in hotplugged.devices {
+        match device.kind {
+            DeviceKind::Share { pid, .. } => pids.push(pid),
+            DeviceKind::Net { tap_device } => tap_devices.push((tap_device, false)),
+            DeviceKind::Disk { .. } => (),
+        }
+    }
+    for pid in pids.into_iter().filter(|pid| runs_in(*pid, &instance.dir)) {
+        _ = send_signal(pid, libc::SIGKILL);
+    }
+
+    let mut errors = vec![];
+    for (name, keep_owner) in tap_devices {
+        if let Err(source) = delete_tap_device(name.clone(), keep_owner).await {
+            errors.push(StopError::DeleteTapDevice { name, source });
+        }
+    }
+
+    if let Err(source) = fs::remove_dir_all(&instance.dir) {
+        errors.push(StopError::DeleteRuntimeDir {
+            id: id.clone(),
+            source,
+        });
+    }
+
+    match errors.len() {
+        0 => Ok(instance),
+        1 => Err(errors.remove(0)),
+        _ => Err(StopError::Cleanup { id, errors }),
+    }
+}